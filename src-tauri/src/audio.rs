@@ -0,0 +1,525 @@
+use std::fs::File;
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Decode an audio file, calling `on_samples` with each block of mono samples
+/// (channels averaged together) and the sample rate. Stops after `max_secs` of audio if
+/// given. Returns the sample rate.
+pub fn decode_mono<F>(
+    file_path: &Path,
+    max_secs: Option<u32>,
+    mut on_samples: F,
+) -> Result<u32, String>
+where
+    F: FnMut(&[f32], u32),
+{
+    let file = File::open(file_path).map_err(|e| format!("Failed to open audio file: {}", e))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = file_path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|e| format!("Unsupported audio format: {}", e))?;
+    let mut format = probed.format;
+
+    let track = format
+        .default_track()
+        .ok_or_else(|| "No audio track found".to_string())?;
+    let track_id = track.id;
+    let sample_rate = track.codec_params.sample_rate.unwrap_or(44_100);
+
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| format!("Unsupported audio codec: {}", e))?;
+
+    let mut sample_buf: Option<SampleBuffer<f32>> = None;
+    let mut mono = Vec::new();
+    let mut remaining = max_secs.map(|secs| secs as u64 * sample_rate as u64);
+
+    while remaining != Some(0) {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            // End of stream
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break
+            }
+            Err(SymphoniaError::ResetRequired) => break,
+            Err(e) => return Err(format!("Failed to read audio packet: {}", e)),
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // Skip corrupt frames rather than failing the whole file
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(format!("Failed to decode audio: {}", e)),
+        };
+
+        let spec = *decoded.spec();
+        let channels = spec.channels.count().max(1);
+        let needed = decoded.capacity() * channels;
+
+        let buf = match &mut sample_buf {
+            Some(buf) if buf.capacity() >= needed => buf,
+            _ => sample_buf.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+        };
+        buf.copy_interleaved_ref(decoded);
+
+        mono.clear();
+        mono.extend(
+            buf.samples()
+                .chunks(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+        );
+
+        if let Some(remaining) = &mut remaining {
+            mono.truncate(mono.len().min(*remaining as usize));
+            *remaining -= mono.len() as u64;
+        }
+
+        on_samples(&mono, spec.rate);
+    }
+
+    Ok(sample_rate)
+}
+
+/// Technical properties of an MP3 stream, read from its frame headers
+#[derive(Debug, Clone, Default)]
+pub struct StreamInfo {
+    pub codec: String,
+    pub bitrate_kbps: u32,
+    pub sample_rate: u32,
+    pub channel_mode: String,
+    pub is_vbr: bool,
+    pub encoder: Option<String>,
+    pub duration_secs: u32,
+}
+
+/// Bitrates in kbps, indexed by [table][bitrate index]
+const BITRATES: [[u32; 15]; 5] = [
+    // MPEG-1 Layer I
+    [
+        0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+    ],
+    // MPEG-1 Layer II
+    [
+        0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+    ],
+    // MPEG-1 Layer III
+    [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ],
+    // MPEG-2/2.5 Layer I
+    [
+        0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+    ],
+    // MPEG-2/2.5 Layer II and III
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+/// A parsed MPEG audio frame header
+struct FrameHeader {
+    is_mpeg1: bool,
+    layer: u8,
+    bitrate_kbps: u32,
+    sample_rate: u32,
+    channel_mode: u8,
+    frame_len: usize,
+    samples_per_frame: u32,
+}
+
+/// Parse the 4-byte frame header at the start of `bytes`
+fn parse_frame_header(bytes: &[u8]) -> Option<FrameHeader> {
+    if bytes.len() < 4 || bytes[0] != 0xFF || bytes[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+
+    let version = (bytes[1] >> 3) & 0x03; // 0 = 2.5, 2 = 2, 3 = 1
+    let layer = match (bytes[1] >> 1) & 0x03 {
+        3 => 1,
+        2 => 2,
+        1 => 3,
+        _ => return None,
+    };
+    let bitrate_index = (bytes[2] >> 4) as usize;
+    let sample_rate_index = ((bytes[2] >> 2) & 0x03) as usize;
+    let padding = ((bytes[2] >> 1) & 0x01) as usize;
+    let channel_mode = bytes[3] >> 6;
+
+    if version == 1 || bitrate_index == 0 || bitrate_index == 15 || sample_rate_index == 3 {
+        return None;
+    }
+
+    let is_mpeg1 = version == 3;
+    let table = match (is_mpeg1, layer) {
+        (true, 1) => 0,
+        (true, 2) => 1,
+        (true, _) => 2,
+        (false, 1) => 3,
+        (false, _) => 4,
+    };
+    let bitrate_kbps = BITRATES[table][bitrate_index];
+
+    let base_rate = [44_100, 48_000, 32_000][sample_rate_index];
+    let sample_rate = match version {
+        3 => base_rate,
+        2 => base_rate / 2,
+        _ => base_rate / 4,
+    };
+
+    let bitrate = bitrate_kbps as usize * 1000;
+    let rate = sample_rate as usize;
+    let (frame_len, samples_per_frame) = match layer {
+        1 => ((12 * bitrate / rate + padding) * 4, 384),
+        2 => (144 * bitrate / rate + padding, 1152),
+        _ if is_mpeg1 => (144 * bitrate / rate + padding, 1152),
+        _ => (72 * bitrate / rate + padding, 576),
+    };
+
+    Some(FrameHeader {
+        is_mpeg1,
+        layer,
+        bitrate_kbps,
+        sample_rate,
+        channel_mode,
+        frame_len,
+        samples_per_frame,
+    })
+}
+
+/// Get the size of a leading ID3v2 tag, if present
+fn id3v2_size(bytes: &[u8]) -> usize {
+    if bytes.len() < 10 || &bytes[0..3] != b"ID3" {
+        return 0;
+    }
+
+    // Size is stored as a 28-bit syncsafe integer
+    let size = bytes[6..10]
+        .iter()
+        .fold(0usize, |acc, &b| (acc << 7) | (b & 0x7F) as usize);
+    let has_footer = bytes[5] & 0x10 != 0;
+
+    10 + size + if has_footer { 10 } else { 0 }
+}
+
+/// Read the Xing/Info/VBRI header from the first frame.
+/// Returns whether the stream is VBR and the encoder string, if any.
+fn read_vbr_header(frame: &[u8], header: &FrameHeader) -> Option<(bool, Option<String>)> {
+    let mono = header.channel_mode == 3;
+    let side_info_len = match (header.is_mpeg1, mono) {
+        (true, false) => 32,
+        (true, true) => 17,
+        (false, false) => 17,
+        (false, true) => 9,
+    };
+
+    let xing_offset = 4 + side_info_len;
+    let tag = frame.get(xing_offset..xing_offset + 4)?;
+
+    if tag == b"Xing" || tag == b"Info" {
+        let flags = u32::from_be_bytes(
+            frame
+                .get(xing_offset + 4..xing_offset + 8)?
+                .try_into()
+                .ok()?,
+        );
+
+        // Skip the optional frame count, byte count, TOC and quality fields
+        let mut lame_offset = xing_offset + 8;
+        for (flag, len) in [(0x1, 4), (0x2, 4), (0x4, 100), (0x8, 4)] {
+            if flags & flag != 0 {
+                lame_offset += len;
+            }
+        }
+
+        let encoder = frame
+            .get(lame_offset..lame_offset + 9)
+            .map(|b| {
+                String::from_utf8_lossy(b)
+                    .trim_end_matches('\0')
+                    .trim()
+                    .to_string()
+            })
+            .filter(|s| !s.is_empty() && s.chars().all(|c| c.is_ascii_graphic()));
+
+        return Some((tag == b"Xing", encoder));
+    }
+
+    // Fraunhofer encoders use a VBRI header at a fixed offset instead
+    if frame.get(36..40) == Some(&b"VBRI"[..]) {
+        return Some((true, Some("Fraunhofer".to_string())));
+    }
+
+    None
+}
+
+/// Check whether a frame belongs to the same stream as another
+fn same_stream(a: &FrameHeader, b: &FrameHeader) -> bool {
+    a.layer == b.layer && a.sample_rate == b.sample_rate
+}
+
+/// Find the first frame at or after `pos`, matching `like` if given. A match must be
+/// followed by a frame of the same stream or the end of the data, so stray sync bytes in
+/// tags or damaged audio aren't taken for a frame.
+fn next_frame(
+    bytes: &[u8],
+    mut pos: usize,
+    like: Option<&FrameHeader>,
+) -> Option<(usize, FrameHeader)> {
+    while pos + 4 <= bytes.len() {
+        let header = parse_frame_header(&bytes[pos..])
+            .filter(|header| like.is_none_or(|like| same_stream(header, like)));
+
+        if let Some(header) = header {
+            let next = pos + header.frame_len;
+            let confirmed = next == bytes.len()
+                || bytes
+                    .get(next..)
+                    .and_then(parse_frame_header)
+                    .is_some_and(|following| same_stream(&following, &header));
+
+            if confirmed {
+                return Some((pos, header));
+            }
+        }
+
+        pos += 1;
+    }
+
+    None
+}
+
+/// Read technical stream properties by walking the MP3 frame headers of a file's contents
+pub fn read_stream_info(bytes: &[u8]) -> Result<StreamInfo, String> {
+    // Find the first frame after any ID3v2 tag
+    let (mut pos, first) = next_frame(bytes, id3v2_size(bytes), None)
+        .ok_or_else(|| "No MPEG audio frames found".to_string())?;
+
+    let vbr_header = read_vbr_header(&bytes[pos..], &first);
+
+    // An Xing/Info/VBRI frame carries no audio, so don't count it
+    if vbr_header.is_some() {
+        pos += first.frame_len;
+    }
+
+    let (mut is_vbr, encoder) = vbr_header.unwrap_or((false, None));
+
+    let mut frame_count: u64 = 0;
+    let mut audio_bytes: u64 = 0;
+    let mut total_samples: u64 = 0;
+
+    loop {
+        let header = bytes
+            .get(pos..)
+            .and_then(parse_frame_header)
+            .filter(|header| same_stream(header, &first));
+
+        // Skip damaged data up to the next frame
+        let header = match header {
+            Some(header) => header,
+            None => match next_frame(bytes, pos + 1, Some(&first)) {
+                Some((next, header)) => {
+                    pos = next;
+                    header
+                }
+                None => break,
+            },
+        };
+
+        if header.bitrate_kbps != first.bitrate_kbps {
+            is_vbr = true;
+        }
+
+        frame_count += 1;
+        audio_bytes += header.frame_len as u64;
+        total_samples += header.samples_per_frame as u64;
+        pos += header.frame_len;
+    }
+
+    let duration = total_samples as f64 / first.sample_rate as f64;
+    let bitrate_kbps = if frame_count > 0 && duration > 0.0 {
+        (audio_bytes as f64 * 8.0 / duration / 1000.0).round() as u32
+    } else {
+        first.bitrate_kbps
+    };
+
+    let codec = match first.layer {
+        1 => "MP1",
+        2 => "MP2",
+        _ => "MP3",
+    };
+
+    let channel_mode = match first.channel_mode {
+        0 => "Stereo",
+        1 => "Joint Stereo",
+        2 => "Dual Channel",
+        _ => "Mono",
+    };
+
+    Ok(StreamInfo {
+        codec: codec.to_string(),
+        bitrate_kbps,
+        sample_rate: first.sample_rate,
+        channel_mode: channel_mode.to_string(),
+        is_vbr,
+        encoder,
+        duration_secs: duration.round() as u32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stereo MPEG-1 Layer III frame at 44.1 kHz with the given bitrate index
+    /// (9 = 128 kbps), filled with zeros
+    fn frame(bitrate_index: u8) -> Vec<u8> {
+        let header = [0xFF, 0xFB, bitrate_index << 4, 0x00];
+        let len = parse_frame_header(&header).unwrap().frame_len;
+
+        let mut frame = header.to_vec();
+        frame.resize(len, 0);
+        frame
+    }
+
+    /// `count` 128 kbps stereo frames
+    fn frames(count: usize) -> Vec<u8> {
+        (0..count).flat_map(|_| frame(9)).collect()
+    }
+
+    /// A first frame carrying a VBR header `tag` at `offset`, followed by `extra` bytes
+    fn header_frame(tag: &[u8], offset: usize, extra: &[u8]) -> Vec<u8> {
+        let mut frame = frame(9);
+        frame[offset..offset + tag.len()].copy_from_slice(tag);
+        frame[offset + tag.len()..offset + tag.len() + extra.len()].copy_from_slice(extra);
+        frame
+    }
+
+    #[test]
+    fn parses_frame_headers() {
+        let header = parse_frame_header(&[0xFF, 0xFB, 0x90, 0x00]).unwrap();
+        assert!(header.is_mpeg1);
+        assert_eq!(header.layer, 3);
+        assert_eq!(header.bitrate_kbps, 128);
+        assert_eq!(header.sample_rate, 44_100);
+        assert_eq!(header.frame_len, 417);
+        assert_eq!(header.samples_per_frame, 1152);
+
+        // MPEG-2 Layer III at 64 kbps, padded, mono
+        let header = parse_frame_header(&[0xFF, 0xF3, 0x82, 0xC0]).unwrap();
+        assert!(!header.is_mpeg1);
+        assert_eq!(header.bitrate_kbps, 64);
+        assert_eq!(header.sample_rate, 22_050);
+        assert_eq!(header.channel_mode, 3);
+        assert_eq!(header.frame_len, 209);
+        assert_eq!(header.samples_per_frame, 576);
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        // No sync, reserved version, free and bad bitrates, reserved sample rate
+        for bytes in [
+            [0xFF, 0x1B, 0x90, 0x00],
+            [0xFF, 0xEB, 0x90, 0x00],
+            [0xFF, 0xFB, 0x00, 0x00],
+            [0xFF, 0xFB, 0xF0, 0x00],
+            [0xFF, 0xFB, 0x9C, 0x00],
+        ] {
+            assert!(parse_frame_header(&bytes).is_none());
+        }
+        assert!(parse_frame_header(&[0xFF, 0xFB, 0x90]).is_none());
+    }
+
+    #[test]
+    fn reads_a_constant_bitrate_stream_after_an_id3_tag() {
+        let mut bytes = b"ID3\x04\x00\x00\x00\x00\x00\x05hello".to_vec();
+        bytes.extend(frames(212));
+
+        let info = read_stream_info(&bytes).unwrap();
+        assert_eq!(info.codec, "MP3");
+        assert_eq!(info.bitrate_kbps, 128);
+        assert_eq!(info.sample_rate, 44_100);
+        assert_eq!(info.channel_mode, "Stereo");
+        assert!(!info.is_vbr);
+        assert_eq!(info.encoder, None);
+        assert_eq!(info.duration_secs, 6);
+    }
+
+    #[test]
+    fn skips_damaged_bytes_and_trailing_tags() {
+        // Junk with a stray sync word between frames, then an ID3v1 tag
+        let mut bytes = frames(106);
+        bytes.extend([0x00, 0xFF, 0xFB, 0x90, 0x12, 0x34]);
+        bytes.extend(frames(106));
+        bytes.extend(b"TAG");
+        bytes.extend([0xFF; 125]);
+
+        let info = read_stream_info(&bytes).unwrap();
+        assert_eq!(info.duration_secs, 6);
+        assert_eq!(info.bitrate_kbps, 128);
+    }
+
+    #[test]
+    fn mixed_bitrates_are_vbr() {
+        let mut bytes = frames(10);
+        bytes.extend(frame(14));
+
+        assert!(read_stream_info(&bytes).unwrap().is_vbr);
+    }
+
+    #[test]
+    fn reads_xing_and_info_headers() {
+        // The tag follows 32 bytes of side info in an MPEG-1 stereo frame; with no optional
+        // fields set, the LAME encoder string comes straight after the flags
+        let mut bytes = header_frame(b"Xing", 36, b"\x00\x00\x00\x00LAME3.100");
+        bytes.extend(frames(212));
+
+        let info = read_stream_info(&bytes).unwrap();
+        assert!(info.is_vbr);
+        assert_eq!(info.encoder.as_deref(), Some("LAME3.100"));
+        // The header frame carries no audio
+        assert_eq!(info.duration_secs, 6);
+
+        // Info marks a constant bitrate file, and the frame count skips 4 bytes
+        let mut bytes = header_frame(b"Info", 36, b"\x00\x00\x00\x01\x00\x00\x00\xD4LAME3.99r");
+        bytes.extend(frames(212));
+
+        let info = read_stream_info(&bytes).unwrap();
+        assert!(!info.is_vbr);
+        assert_eq!(info.encoder.as_deref(), Some("LAME3.99r"));
+    }
+
+    #[test]
+    fn reads_vbri_headers() {
+        let mut bytes = header_frame(b"VBRI", 36, &[]);
+        bytes.extend(frames(10));
+
+        let info = read_stream_info(&bytes).unwrap();
+        assert!(info.is_vbr);
+        assert_eq!(info.encoder.as_deref(), Some("Fraunhofer"));
+    }
+
+    #[test]
+    fn data_without_frames_is_an_error() {
+        assert!(read_stream_info(b"not an mp3 file").is_err());
+        assert!(read_stream_info(&[0xFF, 0xFB, 0x90, 0x00, 0x00]).is_err());
+    }
+}
@@ -0,0 +1,562 @@
+use crate::filesystem;
+use crate::fingerprint;
+use crate::library::Library;
+use crate::models::{AppMetadata, BackupManifest, RestoreMode, RestoreSummary, Song};
+use crate::song_manager;
+use crate::waveform;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Seek};
+use std::path::Path;
+use std::time::SystemTime;
+use tauri::Manager;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Version of the archive layout written by `export_library_backup`
+const BACKUP_FORMAT_VERSION: u32 = 1;
+
+const MANIFEST_ENTRY: &str = "manifest.json";
+const LIBRARY_ENTRY: &str = "library.json";
+const AUDIO_PREFIX: &str = "audio/";
+
+/// Archive entry name for a song's audio file
+fn audio_entry_name(file_path: &str) -> String {
+    format!("{}{}", AUDIO_PREFIX, file_path.replace('\\', "/"))
+}
+
+/// Write the library, and optionally all audio, to a zip archive
+pub fn export_library_backup(
+    dest_path: &Path,
+    include_audio: bool,
+    app_handle: &tauri::AppHandle,
+) -> Result<BackupManifest, String> {
+    let library = app_handle.state::<Library>();
+    let snapshot = library.read(|library| Ok(library.clone()))?;
+
+    write_backup(dest_path, &snapshot, include_audio, library.data_dir())
+}
+
+/// Write a backup archive of `library`, taking audio from the app data directory.
+/// Songs whose audio file is missing are left out and listed in the manifest.
+fn write_backup(
+    dest_path: &Path,
+    library: &AppMetadata,
+    include_audio: bool,
+    data_dir: &Path,
+) -> Result<BackupManifest, String> {
+    let mut manifest = BackupManifest {
+        format_version: BACKUP_FORMAT_VERSION,
+        created_at: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        song_count: library.songs.len(),
+        playlist_count: library.playlists.len(),
+        includes_audio: include_audio,
+        missing_song_ids: Vec::new(),
+    };
+
+    // Write next to the destination and rename, so a failed export never leaves a partial archive
+    let temp_path = dest_path.with_extension("zip.tmp");
+    let file =
+        File::create(&temp_path).map_err(|e| format!("Failed to create backup file: {}", e))?;
+    let mut zip = ZipWriter::new(file);
+
+    let json_options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    // MP3s are already compressed, so store them as-is
+    let audio_options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+
+    let result = (|| -> Result<(), String> {
+        let library_json = serde_json::to_vec_pretty(library)
+            .map_err(|e| format!("Failed to serialize library: {}", e))?;
+        zip.start_file(LIBRARY_ENTRY, json_options)
+            .map_err(|e| format!("Failed to write backup: {}", e))?;
+        std::io::Write::write_all(&mut zip, &library_json)
+            .map_err(|e| format!("Failed to write backup: {}", e))?;
+
+        if include_audio {
+            let all_songs_dir = data_dir.join("music/all_songs");
+            for song in &library.songs {
+                let mut audio = match File::open(all_songs_dir.join(&song.file_path)) {
+                    Ok(audio) => audio,
+                    Err(e) if e.kind() == ErrorKind::NotFound => {
+                        manifest.missing_song_ids.push(song.id.clone());
+                        continue;
+                    }
+                    Err(e) => return Err(format!("Failed to open song file: {}", e)),
+                };
+
+                zip.start_file(audio_entry_name(&song.file_path), audio_options)
+                    .map_err(|e| format!("Failed to write backup: {}", e))?;
+                std::io::copy(&mut audio, &mut zip)
+                    .map_err(|e| format!("Failed to write backup: {}", e))?;
+            }
+        }
+
+        // Written last so it can list the songs left out
+        let manifest_json = serde_json::to_vec_pretty(&manifest)
+            .map_err(|e| format!("Failed to serialize backup manifest: {}", e))?;
+        zip.start_file(MANIFEST_ENTRY, json_options)
+            .map_err(|e| format!("Failed to write backup: {}", e))?;
+        std::io::Write::write_all(&mut zip, &manifest_json)
+            .map_err(|e| format!("Failed to write backup: {}", e))?;
+
+        let file = zip
+            .finish()
+            .map_err(|e| format!("Failed to write backup: {}", e))?;
+        file.sync_all()
+            .map_err(|e| format!("Failed to write backup: {}", e))?;
+
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    fs::rename(&temp_path, dest_path).map_err(|e| format!("Failed to save backup: {}", e))?;
+
+    Ok(manifest)
+}
+
+/// Read a JSON entry from the archive
+fn read_json_entry<R, T>(archive: &mut ZipArchive<R>, name: &str) -> Result<T, String>
+where
+    R: Read + Seek,
+    T: serde::de::DeserializeOwned,
+{
+    let entry = archive
+        .by_name(name)
+        .map_err(|_| format!("Invalid backup: missing {}", name))?;
+
+    serde_json::from_reader(entry).map_err(|e| format!("Invalid backup: bad {}: {}", name, e))
+}
+
+/// Open an archive and check that it's a complete backup this version can restore
+fn read_backup(
+    archive_path: &Path,
+) -> Result<(ZipArchive<File>, BackupManifest, AppMetadata), String> {
+    let file = File::open(archive_path).map_err(|e| format!("Failed to open backup: {}", e))?;
+    let mut archive =
+        ZipArchive::new(file).map_err(|e| format!("Invalid backup: not a zip archive: {}", e))?;
+
+    let manifest: BackupManifest = read_json_entry(&mut archive, MANIFEST_ENTRY)?;
+    if manifest.format_version > BACKUP_FORMAT_VERSION {
+        return Err(format!(
+            "Backup was made by a newer version of the app (format v{})",
+            manifest.format_version
+        ));
+    }
+
+    let library: AppMetadata = read_json_entry(&mut archive, LIBRARY_ENTRY)?;
+    if library.songs.len() != manifest.song_count
+        || library.playlists.len() != manifest.playlist_count
+    {
+        return Err("Invalid backup: library doesn't match its manifest".to_string());
+    }
+
+    let mut song_ids = HashSet::new();
+    for song in &library.songs {
+        if !song_ids.insert(song.id.as_str()) {
+            return Err(format!("Invalid backup: duplicate song {}", song.id));
+        }
+        if !filesystem::is_safe_relative_path(&song.file_path) {
+            return Err(format!("Invalid backup: bad file path {}", song.file_path));
+        }
+        if manifest.includes_audio
+            && !manifest.missing_song_ids.contains(&song.id)
+            && archive
+                .index_for_name(&audio_entry_name(&song.file_path))
+                .is_none()
+        {
+            return Err(format!(
+                "Invalid backup: missing audio for {}",
+                song.file_path
+            ));
+        }
+    }
+
+    let mut playlist_ids = HashSet::new();
+    for playlist in &library.playlists {
+        if !playlist_ids.insert(playlist.id.as_str()) {
+            return Err(format!(
+                "Invalid backup: duplicate playlist {}",
+                playlist.id
+            ));
+        }
+    }
+
+    let mut folder_ids = HashSet::new();
+    for folder in &library.folders {
+        if !folder_ids.insert(folder.id.as_str()) {
+            return Err(format!("Invalid backup: duplicate folder {}", folder.id));
+        }
+    }
+    check_folder_tree(&library)?;
+
+    Ok((archive, manifest, library))
+}
+
+/// Check that every folder's parent is in the backup and no folder ends up inside itself
+fn check_folder_tree(library: &AppMetadata) -> Result<(), String> {
+    for folder in &library.folders {
+        // Walk up to the top level, which a cycle never reaches
+        let mut seen = HashSet::from([folder.id.as_str()]);
+        let mut ancestor = folder.parent_id.as_deref();
+        while let Some(id) = ancestor {
+            if !seen.insert(id) {
+                return Err(format!(
+                    "Invalid backup: folder {} is inside itself",
+                    folder.id
+                ));
+            }
+            ancestor = library
+                .find_folder(id)
+                .ok_or_else(|| format!("Invalid backup: missing parent folder {}", id))?
+                .parent_id
+                .as_deref();
+        }
+    }
+
+    Ok(())
+}
+
+/// Check whether a file on disk is the audio a backed-up song refers to
+fn matches_local_file(song: &Song, path: &Path) -> bool {
+    if !path.is_file() {
+        return false;
+    }
+
+    match (&song.file_hash, song.file_size) {
+        (Some(file_hash), _) => filesystem::hash_file(path).ok().as_ref() == Some(file_hash),
+        (None, Some(file_size)) => fs::metadata(path).map(|m| m.len()).ok() == Some(file_size),
+        (None, None) => true,
+    }
+}
+
+/// Make sure a song's audio is in all_songs, extracting it from the archive if needed.
+/// Returns the song's relative path, which changes if another file already has its name,
+/// or None if the audio is neither on disk nor in the archive.
+fn restore_audio(
+    song: &Song,
+    archive: &mut ZipArchive<File>,
+    data_dir: &Path,
+) -> Result<Option<String>, String> {
+    let local_path = data_dir.join("music/all_songs").join(&song.file_path);
+
+    if matches_local_file(song, &local_path) {
+        return Ok(Some(song.file_path.clone()));
+    }
+
+    let mut entry = match archive.by_name(&audio_entry_name(&song.file_path)) {
+        Ok(entry) => entry,
+        Err(_) => return Ok(None),
+    };
+
+    // Extract under the original file name, then move in with the usual collision handling
+    let file_name = Path::new(&song.file_path)
+        .file_name()
+        .ok_or_else(|| "Invalid song path".to_string())?;
+    let temp_dir = data_dir.join("restore-tmp");
+    fs::create_dir_all(&temp_dir)
+        .map_err(|e| format!("Failed to create restore directory: {}", e))?;
+    let temp_path = temp_dir.join(file_name);
+
+    let mut temp_file =
+        File::create(&temp_path).map_err(|e| format!("Failed to extract song file: {}", e))?;
+    std::io::copy(&mut entry, &mut temp_file)
+        .map_err(|e| format!("Failed to extract song file: {}", e))?;
+    drop(temp_file);
+
+    let dest = filesystem::free_all_songs_path(&temp_path, data_dir, |_| false);
+    let relative_path = dest.and_then(|(dest_path, relative_path)| {
+        fs::rename(&temp_path, dest_path)
+            .map_err(|e| format!("Failed to restore song file: {}", e))?;
+        Ok(relative_path)
+    });
+    let _ = fs::remove_file(&temp_path);
+
+    relative_path.map(Some)
+}
+
+/// Restore a backup archive, either replacing the library or merging into it
+pub fn restore_library_backup(
+    archive_path: &Path,
+    mode: RestoreMode,
+    app_handle: &tauri::AppHandle,
+) -> Result<RestoreSummary, String> {
+    let (summary, restored_songs) =
+        restore_backup(archive_path, mode, &app_handle.state::<Library>())?;
+
+    waveform::generate_in_background(restored_songs.clone(), app_handle.clone());
+    fingerprint::generate_in_background(restored_songs, app_handle.clone());
+
+    Ok(summary)
+}
+
+/// Restore a backup archive into `library`. Returns what changed and the songs restored.
+fn restore_backup(
+    archive_path: &Path,
+    mode: RestoreMode,
+    library: &Library,
+) -> Result<(RestoreSummary, Vec<Song>), String> {
+    let (mut archive, _, backup) = read_backup(archive_path)?;
+
+    let existing_ids: HashSet<String> =
+        library.read(|library| Ok(library.songs.iter().map(|s| s.id.clone()).collect()))?;
+
+    // Put the audio in place first; this is the slow part and doesn't touch the library
+    let mut restored_songs = Vec::new();
+    let mut skipped_song_ids = Vec::new();
+    for song in &backup.songs {
+        if mode == RestoreMode::Merge && existing_ids.contains(&song.id) {
+            continue;
+        }
+
+        match restore_audio(song, &mut archive, library.data_dir())? {
+            Some(file_path) => restored_songs.push(Song {
+                file_path,
+                ..song.clone()
+            }),
+            None => skipped_song_ids.push(song.id.clone()),
+        }
+    }
+
+    // Replacing drops whatever the backup doesn't have, so keep a way back
+    if mode == RestoreMode::Replace {
+        library.take_snapshot()?;
+    }
+
+    let summary = library.write(|library| {
+        let mut summary = RestoreSummary {
+            songs_added: 0,
+            songs_removed: 0,
+            playlists_added: 0,
+            playlists_removed: 0,
+            skipped_song_ids,
+        };
+
+        for song in &restored_songs {
+            let exists = library.find_song(&song.id).is_some();
+
+            // Existing songs keep their own data when merging
+            if exists && mode == RestoreMode::Merge {
+                continue;
+            }
+            if !exists {
+                summary.songs_added += 1;
+            }
+            library.save_song(song)?;
+        }
+
+        if mode == RestoreMode::Replace {
+            for playlist_id in library
+                .playlists
+                .iter()
+                .map(|p| p.id.clone())
+                .collect::<Vec<_>>()
+            {
+                library.delete_playlist(&playlist_id)?;
+                summary.playlists_removed += 1;
+            }
+
+            for folder_id in library
+                .folders
+                .iter()
+                .map(|f| f.id.clone())
+                .collect::<Vec<_>>()
+            {
+                library.delete_folder(&folder_id)?;
+            }
+
+            let keep: HashSet<&str> = restored_songs.iter().map(|s| s.id.as_str()).collect();
+            let removed: Vec<Song> = library
+                .songs
+                .iter()
+                .filter(|s| !keep.contains(s.id.as_str()))
+                .cloned()
+                .collect();
+
+            for song in &removed {
+                library.delete_song(&song.id)?;
+
+                // A restored song may have reused the same file
+                if !library.songs.iter().any(|s| s.file_path == song.file_path) {
+                    song_manager::delete_song_files(song, library)?;
+                }
+            }
+            summary.songs_removed = removed.len();
+        }
+
+        let song_ids: HashSet<String> = library.songs.iter().map(|s| s.id.clone()).collect();
+
+        for folder in &backup.folders {
+            if library.find_folder(&folder.id).is_none() {
+                library.save_folder(folder)?;
+            }
+        }
+
+        for playlist in &backup.playlists {
+            if library.find_playlist(&playlist.id).is_some() {
+                continue;
+            }
+
+            // Drop entries for songs that couldn't be restored
+            let mut playlist = playlist.clone();
+            playlist
+                .entries
+                .retain(|entry| song_ids.contains(&entry.song_id));
+
+            // Merged folders may not match the backup's
+            if let Some(folder_id) = &playlist.folder_id {
+                if library.find_folder(folder_id).is_none() {
+                    playlist.folder_id = None;
+                }
+            }
+
+            library.save_playlist(&playlist)?;
+            summary.playlists_added += 1;
+        }
+
+        Ok(summary)
+    })?;
+
+    Ok((summary, restored_songs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Playlist;
+    use crate::test_fixtures::{folder, open_library, playlist, song};
+
+    /// Back up a library made of the given parts, with no audio, and try to read it back
+    fn read_back(library: AppMetadata) -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("backup.zip");
+        write_backup(&archive_path, &library, false, dir.path()).unwrap();
+
+        read_backup(&archive_path).map(|_| ())
+    }
+
+    #[test]
+    fn round_trip_restores_the_library_without_missing_audio() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source_songs_dir = source_dir.path().join("music/all_songs");
+        fs::create_dir_all(&source_songs_dir).unwrap();
+        fs::write(source_songs_dir.join("a.mp3"), b"a").unwrap();
+
+        // Song b's file is gone
+        let source = AppMetadata {
+            songs: vec![song("a"), song("b")],
+            playlists: vec![Playlist {
+                folder_id: Some("sub".to_string()),
+                ..playlist("mix", &["a", "b"])
+            }],
+            folders: vec![folder("top", None), folder("sub", Some("top"))],
+            ..Default::default()
+        };
+        let archive_path = source_dir.path().join("backup.zip");
+        let manifest = write_backup(&archive_path, &source, true, source_dir.path()).unwrap();
+        assert_eq!(manifest.missing_song_ids, ["b"]);
+
+        let dest_dir = tempfile::tempdir().unwrap();
+        let dest_songs_dir = dest_dir.path().join("music/all_songs");
+        fs::create_dir_all(&dest_songs_dir).unwrap();
+        fs::write(dest_songs_dir.join("old.mp3"), b"old").unwrap();
+        let library = open_library(dest_dir.path());
+        library
+            .write(|library| library.save_song(&song("old")))
+            .unwrap();
+
+        let (summary, restored_songs) =
+            restore_backup(&archive_path, RestoreMode::Replace, &library).unwrap();
+        assert_eq!(summary.songs_added, 1);
+        assert_eq!(summary.songs_removed, 1);
+        assert_eq!(summary.playlists_added, 1);
+        assert_eq!(summary.skipped_song_ids, ["b"]);
+        assert_eq!(restored_songs.len(), 1);
+
+        assert_eq!(fs::read(dest_songs_dir.join("a.mp3")).unwrap(), b"a");
+        assert!(!dest_songs_dir.join("old.mp3").exists());
+
+        library
+            .read(|library| {
+                let song_ids: Vec<&str> = library.songs.iter().map(|s| s.id.as_str()).collect();
+                assert_eq!(song_ids, ["a"]);
+
+                let folder_ids: Vec<&str> = library.folders.iter().map(|f| f.id.as_str()).collect();
+                assert_eq!(folder_ids, ["top", "sub"]);
+
+                let mix = library.get_playlist("mix")?;
+                let entry_ids: Vec<&str> = mix.entries.iter().map(|e| e.song_id.as_str()).collect();
+                assert_eq!(entry_ids, ["a"]);
+                assert_eq!(mix.folder_id.as_deref(), Some("sub"));
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn unsafe_paths_are_rejected() {
+        for file_path in ["../outside.mp3", "/etc/passwd", ""] {
+            let mut bad = song("a");
+            bad.file_path = file_path.to_string();
+
+            let error = read_back(AppMetadata {
+                songs: vec![bad],
+                ..Default::default()
+            })
+            .unwrap_err();
+            assert!(error.contains("bad file path"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn duplicate_ids_are_rejected() {
+        let error = read_back(AppMetadata {
+            songs: vec![song("a"), song("a")],
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(error.contains("duplicate song a"), "{}", error);
+
+        let error = read_back(AppMetadata {
+            playlists: vec![playlist("mix", &[]), playlist("mix", &[])],
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(error.contains("duplicate playlist mix"), "{}", error);
+
+        let error = read_back(AppMetadata {
+            folders: vec![folder("top", None), folder("top", None)],
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(error.contains("duplicate folder top"), "{}", error);
+    }
+
+    #[test]
+    fn broken_folder_trees_are_rejected() {
+        let error = read_back(AppMetadata {
+            folders: vec![folder("sub", Some("gone"))],
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(error.contains("missing parent folder gone"), "{}", error);
+
+        // A folder outside the cycle must not send the check round it forever
+        let error = read_back(AppMetadata {
+            folders: vec![
+                folder("leaf", Some("a")),
+                folder("a", Some("b")),
+                folder("b", Some("a")),
+            ],
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(error.contains("is inside itself"), "{}", error);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::library::Library;
+use crate::models::{Album, AlbumDetail, Artist, ArtistDetail, Song};
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use tauri::Manager;
+
+/// Who an album is filed under: its album artist, or the track artist if untagged
+pub fn album_artist(song: &Song) -> &str {
+    song.album_artist.as_deref().unwrap_or(&song.artist)
+}
+
+/// Derive a stable ID from names, ignoring case so "AC/DC" and "Ac/Dc" group together
+fn make_id(kind: &str, names: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(kind);
+    for name in names {
+        hasher.update([0]);
+        hasher.update(name.to_lowercase());
+    }
+
+    format!("{:x}", hasher.finalize())[..16].to_string()
+}
+
+/// Album order: disc, then track, with untagged discs counted as the first and untagged
+/// tracks last, then title
+fn compare_tracks(a: &Song, b: &Song) -> Ordering {
+    let position = |s: &Song| {
+        (
+            s.disc_number.unwrap_or(1),
+            s.track_number.unwrap_or(u32::MAX),
+        )
+    };
+
+    position(a)
+        .cmp(&position(b))
+        .then_with(|| a.title.to_lowercase().cmp(&b.title.to_lowercase()))
+}
+
+/// Group songs into albums, sorted by artist and then title, each with its songs in order
+fn group_albums(songs: &[Song]) -> Vec<(Album, Vec<&Song>)> {
+    let mut groups: Vec<Vec<&Song>> = Vec::new();
+    let mut index_by_id = HashMap::new();
+
+    for song in songs {
+        let id = make_id("album", &[album_artist(song), &song.album]);
+        let index = *index_by_id.entry(id).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[index].push(song);
+    }
+
+    let mut albums: Vec<(Album, Vec<&Song>)> = groups
+        .into_iter()
+        .map(|mut tracks| {
+            tracks.sort_by(|a, b| compare_tracks(a, b));
+
+            // Names come from the first song seen, since grouping ignores case
+            let first = tracks[0];
+            let album = Album {
+                id: make_id("album", &[album_artist(first), &first.album]),
+                title: first.album.clone(),
+                artist: album_artist(first).to_string(),
+                year: tracks.iter().filter_map(|s| s.year).min(),
+                song_ids: tracks.iter().map(|s| s.id.clone()).collect(),
+                total_duration_secs: tracks
+                    .iter()
+                    .map(|s| s.duration_secs.unwrap_or(0) as u64)
+                    .sum(),
+                album_art: tracks.iter().find_map(|s| s.album_art.clone()),
+            };
+
+            (album, tracks)
+        })
+        .collect();
+
+    albums.sort_by(|(a, _), (b, _)| {
+        a.artist
+            .to_lowercase()
+            .cmp(&b.artist.to_lowercase())
+            .then_with(|| a.title.to_lowercase().cmp(&b.title.to_lowercase()))
+    });
+
+    albums
+}
+
+/// Group albums by artist, sorted by name. Each artist's albums are ordered by year.
+fn group_artists(albums: Vec<Album>) -> Vec<(Artist, Vec<Album>)> {
+    let mut artists: Vec<(Artist, Vec<Album>)> = Vec::new();
+
+    // Albums arrive sorted by artist, so each artist's albums are consecutive
+    for album in albums {
+        let id = make_id("artist", &[&album.artist]);
+        match artists.last_mut() {
+            Some((artist, albums)) if artist.id == id => albums.push(album),
+            _ => artists.push((
+                Artist {
+                    id,
+                    name: album.artist.clone(),
+                    album_ids: Vec::new(),
+                    song_ids: Vec::new(),
+                    total_duration_secs: 0,
+                    album_art: None,
+                },
+                vec![album],
+            )),
+        }
+    }
+
+    for (artist, albums) in artists.iter_mut() {
+        albums.sort_by(|a, b| {
+            a.year
+                .cmp(&b.year)
+                .then_with(|| a.title.to_lowercase().cmp(&b.title.to_lowercase()))
+        });
+
+        artist.album_ids = albums.iter().map(|a| a.id.clone()).collect();
+        artist.song_ids = albums.iter().flat_map(|a| a.song_ids.clone()).collect();
+        artist.total_duration_secs = albums.iter().map(|a| a.total_duration_secs).sum();
+        artist.album_art = albums.iter().find_map(|a| a.album_art.clone());
+    }
+
+    artists
+}
+
+/// Get all albums, sorted by artist and then title
+pub fn get_albums(app_handle: &tauri::AppHandle) -> Result<Vec<Album>, String> {
+    app_handle.state::<Library>().read(|library| {
+        Ok(group_albums(&library.songs)
+            .into_iter()
+            .map(|(album, _)| album)
+            .collect())
+    })
+}
+
+/// Get an album with its songs in disc and track order
+pub fn get_album(album_id: String, app_handle: &tauri::AppHandle) -> Result<AlbumDetail, String> {
+    app_handle.state::<Library>().read(|library| {
+        group_albums(&library.songs)
+            .into_iter()
+            .find(|(album, _)| album.id == album_id)
+            .map(|(album, tracks)| AlbumDetail {
+                album,
+                songs: tracks.into_iter().cloned().collect(),
+            })
+            .ok_or_else(|| format!("Album not found: {}", album_id))
+    })
+}
+
+/// Get all artists, sorted by name
+pub fn get_artists(app_handle: &tauri::AppHandle) -> Result<Vec<Artist>, String> {
+    app_handle.state::<Library>().read(|library| {
+        let albums = group_albums(&library.songs)
+            .into_iter()
+            .map(|(album, _)| album)
+            .collect();
+
+        Ok(group_artists(albums)
+            .into_iter()
+            .map(|(artist, _)| artist)
+            .collect())
+    })
+}
+
+/// Get an artist with their albums and all their songs, album by album
+pub fn get_artist(
+    artist_id: String,
+    app_handle: &tauri::AppHandle,
+) -> Result<ArtistDetail, String> {
+    app_handle.state::<Library>().read(|library| {
+        let albums = group_albums(&library.songs)
+            .into_iter()
+            .map(|(album, _)| album)
+            .collect();
+
+        let (artist, albums) = group_artists(albums)
+            .into_iter()
+            .find(|(artist, _)| artist.id == artist_id)
+            .ok_or_else(|| format!("Artist not found: {}", artist_id))?;
+
+        let songs = artist
+            .song_ids
+            .iter()
+            .map(|id| library.get_song(id).cloned())
+            .collect::<Result<Vec<Song>, String>>()?;
+
+        Ok(ArtistDetail {
+            artist,
+            albums,
+            songs,
+        })
+    })
+}
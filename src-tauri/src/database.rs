@@ -0,0 +1,558 @@
+use crate::filesystem;
+use crate::migrations;
+use crate::models::{
+    AppMetadata, Playlist, PlaylistEntry, PlaylistFolder, Settings, Song, TrashedSong,
+};
+use crate::snapshots;
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Columns selected for every song query, in the order `song_from_row` expects
+const SONG_COLUMNS: &str = "id, title, artist, album, file_path, duration_secs, album_art, \
+     file_hash, codec, bitrate_kbps, sample_rate, channel_mode, is_vbr, encoder, file_size, \
+     genre, year, added_at, track_number, disc_number, album_artist, tags_version";
+
+/// Open a connection to the library database
+pub fn open_database(app_handle: &tauri::AppHandle) -> Result<Connection, String> {
+    let app_data_path = filesystem::get_app_data_path(app_handle)?;
+    let db_path = app_data_path.join("library.db");
+
+    let conn = Connection::open(&db_path)
+        .map_err(|e| format!("Failed to open library database: {}", e))?;
+
+    // Wait on locks held by other connections instead of failing immediately
+    conn.busy_timeout(Duration::from_secs(5))
+        .map_err(|e| format!("Failed to configure library database: {}", e))?;
+
+    // FULL syncs the WAL on every commit so a power loss can't drop committed changes
+    conn.execute_batch(
+        "PRAGMA journal_mode = WAL; PRAGMA synchronous = FULL; PRAGMA foreign_keys = ON;",
+    )
+    .map_err(|e| format!("Failed to configure library database: {}", e))?;
+
+    Ok(conn)
+}
+
+/// Start a transaction so a multi-step change is applied all at once
+pub fn begin(conn: &mut Connection) -> Result<Transaction<'_>, String> {
+    conn.transaction()
+        .map_err(|e| format!("Failed to start transaction: {}", e))
+}
+
+/// Commit a transaction started with `begin`
+pub fn commit(tx: Transaction) -> Result<(), String> {
+    tx.commit()
+        .map_err(|e| format!("Failed to save library changes: {}", e))
+}
+
+/// Restore a corrupt database from its newest snapshot, bring the schema up to date,
+/// import a legacy metadata.json if one exists and snapshot the result.
+/// Returns the connection the library will write through, and the snapshot restored if any.
+pub fn init_database(
+    app_handle: &tauri::AppHandle,
+) -> Result<(Connection, Option<PathBuf>), String> {
+    let restored_from = snapshots::recover_database(app_handle)?;
+
+    let mut conn = open_database(app_handle)?;
+
+    let app_data_path = filesystem::get_app_data_path(app_handle)?;
+    migrations::run_migrations(&mut conn, &app_data_path.join("backups"))?;
+
+    let legacy_path = app_data_path.join("metadata.json");
+
+    if legacy_path.exists() {
+        import_legacy_metadata(&mut conn, &legacy_path)?;
+    }
+
+    snapshots::take_snapshot(&conn, &app_data_path)?;
+
+    Ok((conn, restored_from))
+}
+
+/// Import a metadata.json written by older versions, then set it aside
+fn import_legacy_metadata(conn: &mut Connection, legacy_path: &Path) -> Result<(), String> {
+    let contents = fs::read_to_string(legacy_path)
+        .map_err(|e| format!("Failed to read metadata file: {}", e))?;
+
+    let legacy: AppMetadata = serde_json::from_str(&contents)
+        .map_err(|e| format!("Failed to parse metadata JSON: {}", e))?;
+
+    let tx = begin(conn)?;
+    import_metadata(&tx, &legacy)?;
+    commit(tx)?;
+
+    // Keep the old file around rather than deleting it, in case the import needs redoing
+    fs::rename(legacy_path, legacy_path.with_extension("json.migrated"))
+        .map_err(|e| format!("Failed to rename metadata file: {}", e))?;
+
+    Ok(())
+}
+
+/// Insert all songs and playlists from a metadata snapshot.
+/// Songs and playlists that already exist are left untouched.
+pub fn import_metadata(conn: &Connection, metadata: &AppMetadata) -> Result<(), String> {
+    for song in &metadata.songs {
+        if find_song(conn, &song.id)?.is_none() {
+            save_song(conn, song)?;
+        }
+    }
+
+    // Duplicate playlist IDs keep their first occurrence
+    for playlist in &metadata.playlists {
+        if find_playlist(conn, &playlist.id)?.is_none() {
+            save_playlist(conn, playlist)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn song_from_row(row: &Row) -> rusqlite::Result<Song> {
+    Ok(Song {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        artist: row.get(2)?,
+        album: row.get(3)?,
+        file_path: row.get(4)?,
+        duration_secs: row.get(5)?,
+        album_art: row.get(6)?,
+        file_hash: row.get(7)?,
+        codec: row.get(8)?,
+        bitrate_kbps: row.get(9)?,
+        sample_rate: row.get(10)?,
+        channel_mode: row.get(11)?,
+        is_vbr: row.get(12)?,
+        encoder: row.get(13)?,
+        file_size: row.get(14)?,
+        genre: row.get(15)?,
+        year: row.get(16)?,
+        added_at: row.get(17)?,
+        track_number: row.get(18)?,
+        disc_number: row.get(19)?,
+        album_artist: row.get(20)?,
+        tags_version: row.get(21)?,
+    })
+}
+
+/// Load all songs in library order
+pub fn load_songs(conn: &Connection) -> Result<Vec<Song>, String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM songs ORDER BY rowid",
+            SONG_COLUMNS
+        ))
+        .map_err(|e| format!("Failed to query songs: {}", e))?;
+
+    let songs = stmt
+        .query_map([], song_from_row)
+        .and_then(|rows| rows.collect::<rusqlite::Result<Vec<Song>>>())
+        .map_err(|e| format!("Failed to load songs: {}", e))?;
+
+    Ok(songs)
+}
+
+/// Find a song by ID
+pub fn find_song(conn: &Connection, song_id: &str) -> Result<Option<Song>, String> {
+    conn.query_row(
+        &format!("SELECT {} FROM songs WHERE id = ?1", SONG_COLUMNS),
+        params![song_id],
+        song_from_row,
+    )
+    .optional()
+    .map_err(|e| format!("Failed to load song: {}", e))
+}
+
+/// Insert a song, or update it if it already exists
+pub fn save_song(conn: &Connection, song: &Song) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO songs (id, title, artist, album, file_path, duration_secs, album_art,
+             file_hash, codec, bitrate_kbps, sample_rate, channel_mode, is_vbr, encoder,
+             file_size, genre, year, added_at, track_number, disc_number, album_artist,
+             tags_version)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17,
+             ?18, ?19, ?20, ?21, ?22)
+         ON CONFLICT (id) DO UPDATE SET
+             title = excluded.title,
+             artist = excluded.artist,
+             album = excluded.album,
+             file_path = excluded.file_path,
+             duration_secs = excluded.duration_secs,
+             album_art = excluded.album_art,
+             file_hash = excluded.file_hash,
+             codec = excluded.codec,
+             bitrate_kbps = excluded.bitrate_kbps,
+             sample_rate = excluded.sample_rate,
+             channel_mode = excluded.channel_mode,
+             is_vbr = excluded.is_vbr,
+             encoder = excluded.encoder,
+             file_size = excluded.file_size,
+             genre = excluded.genre,
+             year = excluded.year,
+             added_at = excluded.added_at,
+             track_number = excluded.track_number,
+             disc_number = excluded.disc_number,
+             album_artist = excluded.album_artist,
+             tags_version = excluded.tags_version",
+        params![
+            song.id,
+            song.title,
+            song.artist,
+            song.album,
+            song.file_path,
+            song.duration_secs,
+            song.album_art,
+            song.file_hash,
+            song.codec,
+            song.bitrate_kbps,
+            song.sample_rate,
+            song.channel_mode,
+            song.is_vbr,
+            song.encoder,
+            song.file_size,
+            song.genre,
+            song.year,
+            song.added_at,
+            song.track_number,
+            song.disc_number,
+            song.album_artist,
+            song.tags_version,
+        ],
+    )
+    .map_err(|e| format!("Failed to save song: {}", e))?;
+
+    Ok(())
+}
+
+/// Delete a song row (playlist entries are handled separately)
+pub fn delete_song(conn: &Connection, song_id: &str) -> Result<(), String> {
+    conn.execute("DELETE FROM songs WHERE id = ?1", params![song_id])
+        .map_err(|e| format!("Failed to delete song: {}", e))?;
+
+    Ok(())
+}
+
+/// Load the ordered entries of a playlist
+fn load_playlist_entries(
+    conn: &Connection,
+    playlist_id: &str,
+) -> Result<Vec<PlaylistEntry>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, song_id FROM playlist_entries WHERE playlist_id = ?1 ORDER BY position",
+        )
+        .map_err(|e| format!("Failed to query playlist entries: {}", e))?;
+
+    let entries = stmt
+        .query_map(params![playlist_id], |row| {
+            Ok(PlaylistEntry {
+                id: row.get(0)?,
+                song_id: row.get(1)?,
+            })
+        })
+        .and_then(|rows| rows.collect::<rusqlite::Result<Vec<PlaylistEntry>>>())
+        .map_err(|e| format!("Failed to load playlist entries: {}", e))?;
+
+    Ok(entries)
+}
+
+/// Columns selected for every playlist query, in the order `playlist_from_row` expects
+const PLAYLIST_COLUMNS: &str =
+    "id, name, created_at, smart, folder_id, description, cover, updated_at, pinned";
+
+/// Read a playlist row. Entries are loaded separately by `load_playlist_entries`.
+fn playlist_from_row(row: &Row) -> rusqlite::Result<Playlist> {
+    let smart: Option<String> = row.get(3)?;
+    let smart = smart
+        .map(|json| serde_json::from_str(&json))
+        .transpose()
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(3, Type::Text, Box::new(e)))?;
+
+    Ok(Playlist {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        entries: Vec::new(),
+        created_at: row.get(2)?,
+        smart,
+        folder_id: row.get(4)?,
+        description: row.get(5)?,
+        cover: row.get(6)?,
+        updated_at: row.get(7)?,
+        pinned: row.get(8)?,
+    })
+}
+
+/// Load all playlists in display order
+pub fn load_playlists(conn: &Connection) -> Result<Vec<Playlist>, String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM playlists ORDER BY position",
+            PLAYLIST_COLUMNS
+        ))
+        .map_err(|e| format!("Failed to query playlists: {}", e))?;
+
+    let playlists = stmt
+        .query_map([], playlist_from_row)
+        .and_then(|rows| rows.collect::<rusqlite::Result<Vec<Playlist>>>())
+        .map_err(|e| format!("Failed to load playlists: {}", e))?;
+
+    playlists
+        .into_iter()
+        .map(|mut playlist| {
+            playlist.entries = load_playlist_entries(conn, &playlist.id)?;
+            Ok(playlist)
+        })
+        .collect()
+}
+
+/// Find a playlist by ID
+pub fn find_playlist(conn: &Connection, playlist_id: &str) -> Result<Option<Playlist>, String> {
+    let playlist = conn
+        .query_row(
+            &format!("SELECT {} FROM playlists WHERE id = ?1", PLAYLIST_COLUMNS),
+            params![playlist_id],
+            playlist_from_row,
+        )
+        .optional()
+        .map_err(|e| format!("Failed to load playlist: {}", e))?;
+
+    match playlist {
+        Some(mut playlist) => {
+            playlist.entries = load_playlist_entries(conn, &playlist.id)?;
+            Ok(Some(playlist))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Insert a playlist at the end of the list, or update it if it already exists
+pub fn save_playlist(conn: &Connection, playlist: &Playlist) -> Result<(), String> {
+    let smart = playlist
+        .smart
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| format!("Failed to serialize playlist rules: {}", e))?;
+
+    conn.execute(
+        "INSERT INTO playlists (id, name, created_at, smart, folder_id, description, cover,
+                                updated_at, pinned, position)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9,
+                 (SELECT COALESCE(MAX(position) + 1, 0) FROM playlists))
+         ON CONFLICT (id) DO UPDATE SET
+             name = excluded.name,
+             created_at = excluded.created_at,
+             smart = excluded.smart,
+             folder_id = excluded.folder_id,
+             description = excluded.description,
+             cover = excluded.cover,
+             updated_at = excluded.updated_at,
+             pinned = excluded.pinned",
+        params![
+            playlist.id,
+            playlist.name,
+            playlist.created_at,
+            smart,
+            playlist.folder_id,
+            playlist.description,
+            playlist.cover,
+            playlist.updated_at,
+            playlist.pinned
+        ],
+    )
+    .map_err(|e| format!("Failed to save playlist: {}", e))?;
+
+    conn.execute(
+        "DELETE FROM playlist_entries WHERE playlist_id = ?1",
+        params![playlist.id],
+    )
+    .map_err(|e| format!("Failed to save playlist entries: {}", e))?;
+
+    // A smart playlist's songs come from its rules, so only static playlists store entries
+    if playlist.smart.is_some() {
+        return Ok(());
+    }
+
+    let mut stmt = conn
+        .prepare(
+            "INSERT INTO playlist_entries (playlist_id, position, song_id, id)
+             VALUES (?1, ?2, ?3, ?4)",
+        )
+        .map_err(|e| format!("Failed to save playlist entries: {}", e))?;
+
+    for (position, entry) in playlist.entries.iter().enumerate() {
+        stmt.execute(params![playlist.id, position, entry.song_id, entry.id])
+            .map_err(|e| format!("Failed to save playlist entries: {}", e))?;
+    }
+
+    Ok(())
+}
+
+/// Delete a playlist and its entries
+pub fn delete_playlist(conn: &Connection, playlist_id: &str) -> Result<(), String> {
+    conn.execute("DELETE FROM playlists WHERE id = ?1", params![playlist_id])
+        .map_err(|e| format!("Failed to delete playlist: {}", e))?;
+
+    Ok(())
+}
+
+/// Set the display order of playlists. Every playlist must be listed.
+pub fn set_playlist_order(conn: &Connection, playlist_ids: &[String]) -> Result<(), String> {
+    let mut stmt = conn
+        .prepare("UPDATE playlists SET position = ?1 WHERE id = ?2")
+        .map_err(|e| format!("Failed to reorder playlists: {}", e))?;
+
+    for (position, id) in playlist_ids.iter().enumerate() {
+        stmt.execute(params![position, id])
+            .map_err(|e| format!("Failed to reorder playlists: {}", e))?;
+    }
+
+    Ok(())
+}
+
+/// Load all playlist folders in display order
+pub fn load_folders(conn: &Connection) -> Result<Vec<PlaylistFolder>, String> {
+    let mut stmt = conn
+        .prepare("SELECT id, name, parent_id, created_at FROM playlist_folders ORDER BY position")
+        .map_err(|e| format!("Failed to query playlist folders: {}", e))?;
+
+    stmt.query_map([], |row| {
+        Ok(PlaylistFolder {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            parent_id: row.get(2)?,
+            created_at: row.get(3)?,
+        })
+    })
+    .and_then(|rows| rows.collect::<rusqlite::Result<Vec<PlaylistFolder>>>())
+    .map_err(|e| format!("Failed to load playlist folders: {}", e))
+}
+
+/// Insert a folder at the end of the list, or update it if it already exists
+pub fn save_folder(conn: &Connection, folder: &PlaylistFolder) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO playlist_folders (id, name, parent_id, created_at, position)
+         VALUES (?1, ?2, ?3, ?4, (SELECT COALESCE(MAX(position) + 1, 0) FROM playlist_folders))
+         ON CONFLICT (id) DO UPDATE SET
+             name = excluded.name,
+             parent_id = excluded.parent_id,
+             created_at = excluded.created_at",
+        params![folder.id, folder.name, folder.parent_id, folder.created_at],
+    )
+    .map_err(|e| format!("Failed to save playlist folder: {}", e))?;
+
+    Ok(())
+}
+
+/// Delete a folder (its contents are handled separately)
+pub fn delete_folder(conn: &Connection, folder_id: &str) -> Result<(), String> {
+    conn.execute(
+        "DELETE FROM playlist_folders WHERE id = ?1",
+        params![folder_id],
+    )
+    .map_err(|e| format!("Failed to delete playlist folder: {}", e))?;
+
+    Ok(())
+}
+
+/// Set the display order of folders. Every folder must be listed.
+pub fn set_folder_order(conn: &Connection, folder_ids: &[String]) -> Result<(), String> {
+    let mut stmt = conn
+        .prepare("UPDATE playlist_folders SET position = ?1 WHERE id = ?2")
+        .map_err(|e| format!("Failed to reorder playlist folders: {}", e))?;
+
+    for (position, id) in folder_ids.iter().enumerate() {
+        stmt.execute(params![position, id])
+            .map_err(|e| format!("Failed to reorder playlist folders: {}", e))?;
+    }
+
+    Ok(())
+}
+
+/// Load trashed songs, oldest first
+pub fn load_trash(conn: &Connection) -> Result<Vec<TrashedSong>, String> {
+    let mut stmt = conn
+        .prepare("SELECT song, playlists, deleted_at FROM trash ORDER BY deleted_at, rowid")
+        .map_err(|e| format!("Failed to query trash: {}", e))?;
+
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, u64>(2)?,
+            ))
+        })
+        .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+        .map_err(|e| format!("Failed to load trash: {}", e))?;
+
+    rows.into_iter()
+        .map(|(song, playlists, deleted_at)| {
+            Ok(TrashedSong {
+                song: serde_json::from_str(&song)
+                    .map_err(|e| format!("Failed to parse trashed song: {}", e))?,
+                playlists: serde_json::from_str(&playlists)
+                    .map_err(|e| format!("Failed to parse trashed song: {}", e))?,
+                deleted_at,
+            })
+        })
+        .collect()
+}
+
+/// Add a song to the trash
+pub fn save_trashed_song(conn: &Connection, trashed: &TrashedSong) -> Result<(), String> {
+    let song = serde_json::to_string(&trashed.song)
+        .map_err(|e| format!("Failed to serialize trashed song: {}", e))?;
+    let playlists = serde_json::to_string(&trashed.playlists)
+        .map_err(|e| format!("Failed to serialize trashed song: {}", e))?;
+
+    conn.execute(
+        "INSERT OR REPLACE INTO trash (song_id, song, playlists, deleted_at)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![trashed.song.id, song, playlists, trashed.deleted_at],
+    )
+    .map_err(|e| format!("Failed to save trashed song: {}", e))?;
+
+    Ok(())
+}
+
+/// Remove a song from the trash
+pub fn delete_trashed_song(conn: &Connection, song_id: &str) -> Result<(), String> {
+    conn.execute("DELETE FROM trash WHERE song_id = ?1", params![song_id])
+        .map_err(|e| format!("Failed to delete trashed song: {}", e))?;
+
+    Ok(())
+}
+
+/// Load app settings, using defaults for any that were never set
+pub fn load_settings(conn: &Connection) -> Result<Settings, String> {
+    let mut settings = Settings::default();
+
+    let trash_retention_days: Option<String> = conn
+        .query_row(
+            "SELECT value FROM settings WHERE key = 'trash_retention_days'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| format!("Failed to load settings: {}", e))?;
+
+    if let Some(days) = trash_retention_days.and_then(|v| v.parse().ok()) {
+        settings.trash_retention_days = days;
+    }
+
+    Ok(settings)
+}
+
+/// Save app settings
+pub fn save_settings(conn: &Connection, settings: &Settings) -> Result<(), String> {
+    conn.execute(
+        "INSERT OR REPLACE INTO settings (key, value) VALUES ('trash_retention_days', ?1)",
+        params![settings.trash_retention_days.to_string()],
+    )
+    .map_err(|e| format!("Failed to save settings: {}", e))?;
+
+    Ok(())
+}
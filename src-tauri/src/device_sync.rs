@@ -0,0 +1,383 @@
+use crate::filesystem;
+use crate::library::Library;
+use crate::models::{Playlist, Song, SyncOptions, SyncSummary};
+use crate::playlist_mirror;
+use crate::smart_playlist;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tauri::Manager;
+
+/// Records what earlier syncs wrote, so files the user put on the device are never removed
+const MANIFEST_FILE: &str = ".mp3-player-sync.json";
+
+/// Songs are copied under this folder on the device, laid out as in all_songs
+const MUSIC_DIR: &str = "music";
+
+/// Files written by earlier syncs, by the playlist they were synced for. Paths are relative
+/// to the target directory, with '/' separators. A song in several playlists is listed
+/// under each.
+#[derive(Default, Serialize, Deserialize)]
+struct SyncManifest {
+    playlists: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl SyncManifest {
+    /// Check whether any playlist still needs a file
+    fn contains(&self, file: &str) -> bool {
+        self.playlists.values().any(|files| files.contains(file))
+    }
+}
+
+fn read_manifest(target_dir: &Path) -> SyncManifest {
+    fs::read_to_string(target_dir.join(MANIFEST_FILE))
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn write_manifest(target_dir: &Path, manifest: &SyncManifest) -> Result<(), String> {
+    let contents = serde_json::to_string_pretty(manifest)
+        .map_err(|e| format!("Failed to serialize sync manifest: {}", e))?;
+
+    fs::write(target_dir.join(MANIFEST_FILE), contents)
+        .map_err(|e| format!("Failed to write sync manifest: {}", e))
+}
+
+/// Path of a song on the device, relative to the target directory
+fn device_song_path(song: &Song) -> String {
+    format!("{}/{}", MUSIC_DIR, song.file_path.replace('\\', "/"))
+}
+
+/// Check whether the copy on the device is already up to date
+fn is_unchanged(source: &Path, dest: &Path) -> bool {
+    let (source_meta, dest_meta) = match (fs::metadata(source), fs::metadata(dest)) {
+        (Ok(source_meta), Ok(dest_meta)) => (source_meta, dest_meta),
+        _ => return false,
+    };
+
+    // Copies get a fresh modified time, so a newer source means it changed since
+    match (source_meta.modified(), dest_meta.modified()) {
+        (Ok(source_time), Ok(dest_time)) => {
+            source_meta.len() == dest_meta.len() && dest_time >= source_time
+        }
+        _ => false,
+    }
+}
+
+/// Write an extended M3U playlist with paths relative to the target directory.
+/// Saved as .m3u8, since names aren't always ASCII and players read plain .m3u as Latin-1.
+fn write_m3u(
+    path: &Path,
+    playlist: &Playlist,
+    songs: &HashMap<&str, &Song>,
+    synced: &BTreeSet<String>,
+) -> Result<(), String> {
+    let mut contents = String::from("#EXTM3U\n");
+    contents.push_str(&format!("#PLAYLIST:{}\n", playlist.name));
+
+    for entry in &playlist.entries {
+        let song = match songs.get(entry.song_id.as_str()) {
+            Some(song) => song,
+            None => continue,
+        };
+
+        let song_path = device_song_path(song);
+        if !synced.contains(&song_path) {
+            continue;
+        }
+
+        contents.push_str(&format!(
+            "#EXTINF:{},{} - {}\n{}\n",
+            song.duration_secs.map(i64::from).unwrap_or(-1),
+            song.artist,
+            song.title,
+            song_path
+        ));
+    }
+
+    let mut file = File::create(path).map_err(|e| format!("Failed to write playlist: {}", e))?;
+    file.write_all(contents.as_bytes())
+        .map_err(|e| format!("Failed to write playlist: {}", e))
+}
+
+/// Remove empty folders left under `dir` after stale songs were deleted
+fn remove_empty_dirs(dir: &Path) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.filter_map(|e| e.ok()) {
+            if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                remove_empty_dirs(&entry.path());
+                // Fails, as intended, unless the folder is now empty
+                let _ = fs::remove_dir(entry.path());
+            }
+        }
+    }
+}
+
+/// Copy the songs of the given playlists to a folder such as a mounted player,
+/// with an M3U file per playlist. Songs already there and unchanged are skipped.
+pub fn sync_to_device(
+    target_dir: &Path,
+    playlist_ids: Vec<String>,
+    options: SyncOptions,
+    app_handle: &tauri::AppHandle,
+) -> Result<SyncSummary, String> {
+    if !target_dir.is_dir() {
+        return Err(format!(
+            "Sync target is not a directory: {}",
+            target_dir.display()
+        ));
+    }
+
+    // Work from a snapshot so copying doesn't hold up the library
+    let (playlists, songs, dir_names) = app_handle.state::<Library>().read(|library| {
+        let playlists = playlist_ids
+            .iter()
+            .map(|id| smart_playlist::resolve(library.get_playlist(id)?.clone(), &library.songs))
+            .collect::<Result<Vec<Playlist>, String>>()?;
+
+        Ok((
+            playlists,
+            library.songs.clone(),
+            playlist_mirror::dir_names(library),
+        ))
+    })?;
+
+    let device = Device {
+        target_dir: target_dir.to_path_buf(),
+        all_songs_dir: filesystem::get_app_data_path(app_handle)?.join("music/all_songs"),
+        dir_names,
+    };
+
+    device.sync(&playlists, &songs, options)
+}
+
+/// Where a sync reads songs from and writes them to
+struct Device {
+    target_dir: PathBuf,
+    all_songs_dir: PathBuf,
+    dir_names: HashMap<String, String>, // M3U file name for each playlist, by ID
+}
+
+impl Device {
+    /// Copy the playlists' songs and write their M3U files
+    fn sync(
+        &self,
+        playlists: &[Playlist],
+        songs: &[Song],
+        options: SyncOptions,
+    ) -> Result<SyncSummary, String> {
+        let target_dir = self.target_dir.as_path();
+        let songs_by_id: HashMap<&str, &Song> = songs.iter().map(|s| (s.id.as_str(), s)).collect();
+        let previous = read_manifest(target_dir);
+
+        let mut summary = SyncSummary {
+            copied: 0,
+            unchanged: 0,
+            removed: 0,
+            playlists_written: 0,
+            missing_song_ids: Vec::new(),
+        };
+        let mut synced = BTreeSet::new();
+
+        for entry in playlists.iter().flat_map(|p| &p.entries) {
+            let song = match songs_by_id.get(entry.song_id.as_str()) {
+                Some(song) => song,
+                None => continue,
+            };
+
+            let relative_path = device_song_path(song);
+            if synced.contains(&relative_path) {
+                continue;
+            }
+
+            let source = self.all_songs_dir.join(&song.file_path);
+            if !source.is_file() {
+                if !summary.missing_song_ids.contains(&song.id) {
+                    summary.missing_song_ids.push(song.id.clone());
+                }
+                continue;
+            }
+
+            let dest = target_dir.join(&relative_path);
+            if is_unchanged(&source, &dest) {
+                summary.unchanged += 1;
+            } else {
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent)
+                        .map_err(|e| format!("Failed to create folder on device: {}", e))?;
+                }
+                fs::copy(&source, &dest).map_err(|e| format!("Failed to copy song: {}", e))?;
+                summary.copied += 1;
+            }
+
+            synced.insert(relative_path);
+        }
+
+        // Playlists not synced this time keep what they had
+        let mut manifest = SyncManifest {
+            playlists: previous.playlists.clone(),
+        };
+        for playlist in playlists {
+            let file_name = format!("{}.m3u8", self.dir_names[&playlist.id]);
+            write_m3u(
+                &target_dir.join(&file_name),
+                playlist,
+                &songs_by_id,
+                &synced,
+            )?;
+            summary.playlists_written += 1;
+
+            let mut files: BTreeSet<String> = playlist
+                .entries
+                .iter()
+                .filter_map(|entry| songs_by_id.get(entry.song_id.as_str()))
+                .map(|song| device_song_path(song))
+                .filter(|path| synced.contains(path))
+                .collect();
+            files.insert(file_name);
+            manifest.playlists.insert(playlist.id.clone(), files);
+        }
+
+        // Only ever remove what an earlier sync wrote for the playlists synced now,
+        // and only once no playlist on the device needs it
+        let stale_files: BTreeSet<String> = playlists
+            .iter()
+            .filter_map(|playlist| previous.playlists.get(&playlist.id))
+            .flatten()
+            .filter(|file| !manifest.contains(file))
+            .cloned()
+            .collect();
+
+        for stale in &stale_files {
+            // The manifest lives on the device, so don't trust it to stay inside the target
+            if !filesystem::is_safe_relative_path(stale) {
+                continue;
+            }
+
+            let path = target_dir.join(stale);
+            if !path.is_file() {
+                continue;
+            }
+
+            if options.remove_stale {
+                fs::remove_file(&path).map_err(|e| format!("Failed to remove {}: {}", stale, e))?;
+                summary.removed += 1;
+            } else {
+                // Keep tracking it so a later sync can still remove it
+                for playlist in playlists {
+                    let had_it = previous
+                        .playlists
+                        .get(&playlist.id)
+                        .is_some_and(|files| files.contains(stale));
+                    if had_it {
+                        manifest
+                            .playlists
+                            .entry(playlist.id.clone())
+                            .or_default()
+                            .insert(stale.clone());
+                    }
+                }
+            }
+        }
+
+        if options.remove_stale {
+            remove_empty_dirs(&target_dir.join(MUSIC_DIR));
+        }
+
+        write_manifest(target_dir, &manifest)?;
+
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{playlist, song};
+
+    /// A library of songs a, b and c, and an empty device to sync them to
+    fn setup(library_dir: &Path, target_dir: &Path) -> (Device, Vec<Song>) {
+        let all_songs_dir = library_dir.join("all_songs");
+        fs::create_dir_all(&all_songs_dir).unwrap();
+
+        let songs: Vec<Song> = ["a", "b", "c"].iter().map(|id| song(id)).collect();
+        for song in &songs {
+            fs::write(all_songs_dir.join(&song.file_path), &song.id).unwrap();
+        }
+
+        let device = Device {
+            target_dir: target_dir.to_path_buf(),
+            all_songs_dir,
+            dir_names: [("A", "A"), ("B", "B")]
+                .iter()
+                .map(|(id, name)| (id.to_string(), name.to_string()))
+                .collect(),
+        };
+
+        (device, songs)
+    }
+
+    fn remove_stale() -> SyncOptions {
+        SyncOptions { remove_stale: true }
+    }
+
+    #[test]
+    fn copies_songs_then_skips_unchanged() {
+        let library_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+        let (device, songs) = setup(library_dir.path(), target_dir.path());
+        let playlists = [playlist("A", &["a", "b"])];
+
+        let first = device
+            .sync(&playlists, &songs, SyncOptions::default())
+            .unwrap();
+        assert_eq!((first.copied, first.unchanged), (2, 0));
+        assert!(target_dir.path().join("music/a.mp3").is_file());
+        assert!(target_dir.path().join("music/b.mp3").is_file());
+
+        let m3u = fs::read_to_string(target_dir.path().join("A.m3u8")).unwrap();
+        assert!(m3u.ends_with("music/a.mp3\n#EXTINF:-1,Artist - b\nmusic/b.mp3\n"));
+
+        let second = device
+            .sync(&playlists, &songs, SyncOptions::default())
+            .unwrap();
+        assert_eq!((second.copied, second.unchanged), (0, 2));
+    }
+
+    #[test]
+    fn removes_only_files_synced_for_the_playlists_synced_now() {
+        let library_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+        let (device, songs) = setup(library_dir.path(), target_dir.path());
+        let music_dir = target_dir.path().join("music");
+
+        device
+            .sync(&[playlist("A", &["a", "b"])], &songs, remove_stale())
+            .unwrap();
+
+        // Something the user put on the device themselves
+        fs::write(music_dir.join("mine.mp3"), "mine").unwrap();
+
+        // Syncing another playlist leaves A's files alone
+        let summary = device
+            .sync(&[playlist("B", &["c"])], &songs, remove_stale())
+            .unwrap();
+        assert_eq!(summary.removed, 0);
+        assert!(music_dir.join("a.mp3").is_file());
+        assert!(music_dir.join("b.mp3").is_file());
+        assert!(target_dir.path().join("A.m3u8").is_file());
+
+        // Dropping b from A removes it, but nothing the sync didn't write
+        let summary = device
+            .sync(&[playlist("A", &["a"])], &songs, remove_stale())
+            .unwrap();
+        assert_eq!(summary.removed, 1);
+        assert!(music_dir.join("a.mp3").is_file());
+        assert!(!music_dir.join("b.mp3").exists());
+        assert!(music_dir.join("c.mp3").is_file());
+        assert!(music_dir.join("mine.mp3").is_file());
+    }
+}
@@ -1,5 +1,6 @@
+use sha2::{Digest, Sha256};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use tauri::Manager;
 use walkdir::WalkDir;
 
@@ -26,16 +27,6 @@ pub fn init_app_data_dir(app_handle: &tauri::AppHandle) -> Result<(), String> {
     fs::create_dir_all(&playlists_dir)
         .map_err(|e| format!("Failed to create playlists directory: {}", e))?;
 
-    // Create metadata.json if it doesn't exist
-    let metadata_path = app_data_path.join("metadata.json");
-    if !metadata_path.exists() {
-        let empty_metadata = crate::models::AppMetadata::new();
-        let json = serde_json::to_string_pretty(&empty_metadata)
-            .map_err(|e| format!("Failed to serialize metadata: {}", e))?;
-        fs::write(&metadata_path, json)
-            .map_err(|e| format!("Failed to write metadata file: {}", e))?;
-    }
-
     Ok(())
 }
 
@@ -48,30 +39,65 @@ pub fn copy_file_to_all_songs(
     let app_data_path = get_app_data_path(app_handle)?;
     let all_songs_dir = app_data_path.join("music/all_songs");
 
+    let dest_path = unique_dest_path(source, &all_songs_dir, |_| false)?;
+
+    // Copy the file
+    fs::copy(source, &dest_path)
+        .map_err(|e| format!("Failed to copy file: {}", e))?;
+
+    relative_to(&dest_path, &all_songs_dir)
+}
+
+/// Pick a free path in the all_songs directory for a file, without moving it there.
+/// Paths `in_use` reports, relative to all_songs/, count as taken even if no file is
+/// there yet. Returns the absolute path and the path relative to all_songs/
+pub fn free_all_songs_path<F>(
+    source: &Path,
+    data_dir: &Path,
+    in_use: F,
+) -> Result<(PathBuf, String), String>
+where
+    F: Fn(&str) -> bool,
+{
+    let all_songs_dir = data_dir.join("music/all_songs");
+
+    let dest_path = unique_dest_path(source, &all_songs_dir, |path| {
+        relative_to(path, &all_songs_dir).is_ok_and(|relative_path| in_use(&relative_path))
+    })?;
+    let relative_path = relative_to(&dest_path, &all_songs_dir)?;
+
+    Ok((dest_path, relative_path))
+}
+
+/// Pick a path in `dir` for `source`'s file name, adding a counter if it's taken
+fn unique_dest_path<F>(source: &Path, dir: &Path, in_use: F) -> Result<PathBuf, String>
+where
+    F: Fn(&Path) -> bool,
+{
     // Get the filename
     let filename = source
         .file_name()
         .ok_or_else(|| "Invalid source file path".to_string())?;
 
     // Handle duplicate filenames
-    let mut dest_path = all_songs_dir.join(filename);
+    let mut dest_path = dir.join(filename);
     let mut counter = 1;
 
-    while dest_path.exists() {
+    while dest_path.exists() || in_use(&dest_path) {
         let stem = source.file_stem().and_then(|s| s.to_str()).unwrap_or("song");
         let ext = source.extension().and_then(|s| s.to_str()).unwrap_or("mp3");
         let new_filename = format!("{}_{}.{}", stem, counter, ext);
-        dest_path = all_songs_dir.join(new_filename);
+        dest_path = dir.join(new_filename);
         counter += 1;
     }
 
-    // Copy the file
-    fs::copy(source, &dest_path)
-        .map_err(|e| format!("Failed to copy file: {}", e))?;
+    Ok(dest_path)
+}
 
-    // Return relative path
-    let relative_path = dest_path
-        .strip_prefix(&all_songs_dir)
+/// Get a path relative to `base` as a string
+fn relative_to(path: &Path, base: &Path) -> Result<String, String> {
+    let relative_path = path
+        .strip_prefix(base)
         .map_err(|e| format!("Failed to get relative path: {}", e))?
         .to_str()
         .ok_or_else(|| "Invalid UTF-8 in path".to_string())?
@@ -80,48 +106,31 @@ pub fn copy_file_to_all_songs(
     Ok(relative_path)
 }
 
-/// Create a symlink in a playlist folder
-#[allow(unused_variables)]
-pub fn create_playlist_symlink(
-    song_path: &Path,
-    playlist_name: &str,
-    app_handle: &tauri::AppHandle,
-) -> Result<(), String> {
-    let app_data_path = get_app_data_path(app_handle)?;
-    let playlist_dir = app_data_path.join("music/playlists").join(playlist_name);
-
-    // Create playlist directory if it doesn't exist
-    fs::create_dir_all(&playlist_dir)
-        .map_err(|e| format!("Failed to create playlist directory: {}", e))?;
-
-    let song_filename = song_path
-        .file_name()
-        .ok_or_else(|| "Invalid song path".to_string())?;
-
-    let link_path = playlist_dir.join(song_filename);
-
-    // Skip if symlink already exists
-    if link_path.exists() {
-        return Ok(());
-    }
-
+/// Link a song file into a playlist folder: a symlink, or a hard link on Windows
+pub fn create_link(song_path: &Path, link_path: &Path) -> Result<(), String> {
     // Create symlink (platform-specific)
     #[cfg(unix)]
     {
-        std::os::unix::fs::symlink(song_path, &link_path)
+        std::os::unix::fs::symlink(song_path, link_path)
             .map_err(|e| format!("Failed to create symlink: {}", e))?;
     }
 
     #[cfg(windows)]
     {
         // On Windows, we'll use hardlinks instead of symlinks (no admin rights needed)
-        std::fs::hard_link(song_path, &link_path)
+        std::fs::hard_link(song_path, link_path)
             .map_err(|e| format!("Failed to create hard link: {}", e))?;
     }
 
     Ok(())
 }
 
+/// Check that a relative path stays inside the directory it's joined to
+pub fn is_safe_relative_path(relative_path: &str) -> bool {
+    let path = Path::new(relative_path);
+    path.components().count() > 0 && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
 /// Scan a directory recursively for MP3 files
 pub fn scan_directory_for_mp3s(dir: &Path) -> Result<Vec<PathBuf>, String> {
     let mut mp3_files = Vec::new();
@@ -158,3 +167,19 @@ pub fn get_song_absolute_path(
 
     Ok(absolute_path)
 }
+
+/// Compute the SHA-256 hash of a file as a hex string
+pub fn hash_file(file_path: &Path) -> Result<String, String> {
+    let mut file =
+        fs::File::open(file_path).map_err(|e| format!("Failed to open file for hashing: {}", e))?;
+
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher).map_err(|e| format!("Failed to hash file: {}", e))?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Compute the SHA-256 hash of a file's contents, already read, as a hex string
+pub fn hash_bytes(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
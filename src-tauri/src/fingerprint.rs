@@ -0,0 +1,498 @@
+use crate::audio;
+use crate::filesystem;
+use crate::library::Library;
+use crate::metadata;
+use crate::models::{DuplicateGroup, DuplicateMatch, Fingerprint, Song};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::Manager;
+
+/// Sample rate the audio is reduced to before analysis
+const TARGET_SAMPLE_RATE: u32 = 11_025;
+
+/// Samples per analysis frame (must be a power of two for the FFT)
+const FRAME_SIZE: usize = 4096;
+
+/// Samples between the starts of consecutive frames
+const HOP_SIZE: usize = FRAME_SIZE / 3;
+
+/// Only the start of each song is analyzed
+const MAX_ANALYZED_SECS: u32 = 120;
+
+/// Frequency range mapped onto the chroma bands
+const MIN_FREQ: f32 = 28.0;
+const MAX_FREQ: f32 = 3520.0;
+
+/// Songs whose durations differ by more than this are never compared
+const MAX_DURATION_DIFF_SECS: u32 = 10;
+
+/// Maximum alignment shift tried when comparing fingerprints (about 5 seconds)
+const MAX_OFFSET: usize = 40;
+
+/// Minimum number of overlapping frames needed for a meaningful comparison
+const MIN_OVERLAP: usize = 50;
+
+/// Default similarity above which two songs are considered duplicates
+pub const DEFAULT_THRESHOLD: f32 = 0.75;
+
+/// In-place iterative radix-2 FFT over separate real and imaginary buffers
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+
+    // Bit-reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        let (w_im, w_re) = angle.sin_cos();
+
+        for start in (0..n).step_by(len) {
+            let mut cur_re = 1.0f32;
+            let mut cur_im = 0.0f32;
+
+            for k in 0..len / 2 {
+                let a = start + k;
+                let b = a + len / 2;
+                let t_re = re[b] * cur_re - im[b] * cur_im;
+                let t_im = re[b] * cur_im + im[b] * cur_re;
+
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+
+                let next_re = cur_re * w_re - cur_im * w_im;
+                cur_im = cur_re * w_im + cur_im * w_re;
+                cur_re = next_re;
+            }
+        }
+
+        len <<= 1;
+    }
+}
+
+/// Compute the normalized 12-band chroma vector of one frame
+fn chroma_frame(frame: &[f32], window: &[f32], sample_rate: u32) -> [f32; 12] {
+    let mut re: Vec<f32> = frame.iter().zip(window).map(|(s, w)| s * w).collect();
+    let mut im = vec![0.0; FRAME_SIZE];
+    fft(&mut re, &mut im);
+
+    let mut chroma = [0.0f32; 12];
+    for bin in 1..FRAME_SIZE / 2 {
+        let freq = bin as f32 * sample_rate as f32 / FRAME_SIZE as f32;
+        if !(MIN_FREQ..=MAX_FREQ).contains(&freq) {
+            continue;
+        }
+
+        // Map the bin to its pitch class (A = 0)
+        let note = 12.0 * (freq / 440.0).log2();
+        let class = (note.round() as i32).rem_euclid(12) as usize;
+        chroma[class] += re[bin] * re[bin] + im[bin] * im[bin];
+    }
+
+    let norm = chroma.iter().map(|c| c * c).sum::<f32>().sqrt();
+    if norm > 1e-6 {
+        for c in &mut chroma {
+            *c /= norm;
+        }
+    }
+
+    chroma
+}
+
+/// Pack a 32-bit sub-fingerprint from how the chroma changes across pitch and time
+fn sub_fingerprint(previous: &[f32; 12], current: &[f32; 12]) -> u32 {
+    let mut bits = 0u32;
+
+    for i in 0..12 {
+        // Rising or falling energy over time
+        if current[i] > previous[i] {
+            bits |= 1 << i;
+        }
+        // Energy compared with the neighbouring pitch class
+        if current[i] > current[(i + 1) % 12] {
+            bits |= 1 << (12 + i);
+        }
+    }
+
+    for i in 0..8 {
+        // Energy compared with the pitch class a third above
+        if current[i] > current[i + 4] {
+            bits |= 1 << (24 + i);
+        }
+    }
+
+    bits
+}
+
+/// Compute one sub-fingerprint per analysis frame of mono samples
+fn frame_hashes(samples: &[f32], sample_rate: u32) -> Vec<u32> {
+    // Hann window
+    let window: Vec<f32> = (0..FRAME_SIZE)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (FRAME_SIZE - 1) as f32).cos())
+        .collect();
+
+    let mut hashes = Vec::new();
+    let mut previous: Option<[f32; 12]> = None;
+    let mut start = 0;
+
+    while start + FRAME_SIZE <= samples.len() {
+        let chroma = chroma_frame(&samples[start..start + FRAME_SIZE], &window, sample_rate);
+
+        if let Some(previous) = &previous {
+            hashes.push(sub_fingerprint(previous, &chroma));
+        }
+
+        previous = Some(chroma);
+        start += HOP_SIZE;
+    }
+
+    hashes
+}
+
+/// Decode an audio file and compute its fingerprint. Only the start is decoded when the
+/// song's duration is known; otherwise the whole file is, to measure it.
+pub fn compute_fingerprint(
+    file_path: &Path,
+    duration_secs: Option<u32>,
+) -> Result<Fingerprint, String> {
+    let mut samples = Vec::new();
+    let mut effective_rate = TARGET_SAMPLE_RATE;
+    let mut total_samples: u64 = 0;
+    let mut acc = 0.0f32;
+    let mut acc_count = 0;
+
+    let max_secs = duration_secs.map(|_| MAX_ANALYZED_SECS);
+    let sample_rate = audio::decode_mono(file_path, max_secs, |block, sample_rate| {
+        total_samples += block.len() as u64;
+
+        // Average groups of samples to get roughly down to the target rate
+        let factor = (sample_rate / TARGET_SAMPLE_RATE).max(1) as usize;
+        effective_rate = sample_rate / factor as u32;
+        let max_len = (effective_rate * MAX_ANALYZED_SECS) as usize;
+
+        for &sample in block {
+            if samples.len() >= max_len {
+                break;
+            }
+
+            acc += sample;
+            acc_count += 1;
+            if acc_count == factor {
+                samples.push(acc / factor as f32);
+                acc = 0.0;
+                acc_count = 0;
+            }
+        }
+    })?;
+
+    let duration_secs = duration_secs.unwrap_or((total_samples / sample_rate.max(1) as u64) as u32);
+
+    Ok(Fingerprint {
+        duration_secs,
+        hashes: frame_hashes(&samples, effective_rate),
+    })
+}
+
+/// Compare two fingerprints, returning a similarity between 0.0 and 1.0
+pub fn compare_fingerprints(a: &Fingerprint, b: &Fingerprint) -> f32 {
+    let mut best = 0.0f32;
+
+    // Try small shifts in both directions to allow for differing lead-in silence
+    for offset in -(MAX_OFFSET as isize)..=MAX_OFFSET as isize {
+        let (a_start, b_start) = if offset >= 0 {
+            (offset as usize, 0)
+        } else {
+            (0, (-offset) as usize)
+        };
+
+        if a_start >= a.hashes.len() || b_start >= b.hashes.len() {
+            continue;
+        }
+
+        let overlap = (a.hashes.len() - a_start).min(b.hashes.len() - b_start);
+        if overlap < MIN_OVERLAP {
+            continue;
+        }
+
+        let differing_bits: u32 = a.hashes[a_start..a_start + overlap]
+            .iter()
+            .zip(&b.hashes[b_start..b_start + overlap])
+            .map(|(x, y)| (x ^ y).count_ones())
+            .sum();
+
+        // Unrelated audio differs in about half the bits, so scale that to zero
+        let bit_error_rate = differing_bits as f32 / (overlap as f32 * 32.0);
+        let similarity = (1.0 - 2.0 * bit_error_rate).clamp(0.0, 1.0);
+
+        best = best.max(similarity);
+    }
+
+    best
+}
+
+/// Get the path of the cached fingerprint for a file hash
+fn fingerprint_cache_path(file_hash: &str, data_dir: &Path) -> PathBuf {
+    data_dir
+        .join("fingerprints")
+        .join(format!("{}.json", file_hash))
+}
+
+/// Load a cached fingerprint, if one exists
+fn load_cached_fingerprint(
+    file_hash: &str,
+    app_handle: &tauri::AppHandle,
+) -> Result<Option<Fingerprint>, String> {
+    let data_dir = filesystem::get_app_data_path(app_handle)?;
+    let cache_path = fingerprint_cache_path(file_hash, &data_dir);
+
+    if !cache_path.exists() {
+        return Ok(None);
+    }
+
+    let contents = fs::read_to_string(&cache_path)
+        .map_err(|e| format!("Failed to read fingerprint cache: {}", e))?;
+
+    // A corrupt cache entry is simply regenerated
+    Ok(serde_json::from_str(&contents).ok())
+}
+
+/// Save a fingerprint to the cache
+fn save_cached_fingerprint(
+    file_hash: &str,
+    fingerprint: &Fingerprint,
+    app_handle: &tauri::AppHandle,
+) -> Result<(), String> {
+    let data_dir = filesystem::get_app_data_path(app_handle)?;
+    let cache_path = fingerprint_cache_path(file_hash, &data_dir);
+
+    if let Some(parent) = cache_path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create fingerprint directory: {}", e))?;
+    }
+
+    let json = serde_json::to_string(fingerprint)
+        .map_err(|e| format!("Failed to serialize fingerprint: {}", e))?;
+
+    fs::write(&cache_path, json)
+        .map_err(|e| format!("Failed to write fingerprint cache: {}", e))?;
+
+    Ok(())
+}
+
+/// Get the fingerprint for a song, computing and caching it if needed
+fn ensure_fingerprint(song: &Song, app_handle: &tauri::AppHandle) -> Result<Fingerprint, String> {
+    let file_hash = metadata::ensure_file_hash(song, app_handle)?;
+
+    if let Some(fingerprint) = load_cached_fingerprint(&file_hash, app_handle)? {
+        return Ok(fingerprint);
+    }
+
+    let absolute_path = filesystem::get_song_absolute_path(&song.file_path, app_handle)?;
+    let fingerprint = compute_fingerprint(&absolute_path, song.duration_secs)?;
+    save_cached_fingerprint(&file_hash, &fingerprint, app_handle)?;
+
+    Ok(fingerprint)
+}
+
+/// Compute missing fingerprints for the given songs on a background thread
+pub fn generate_in_background(songs: Vec<Song>, app_handle: tauri::AppHandle) {
+    tauri::async_runtime::spawn_blocking(move || {
+        for song in songs {
+            if let Err(e) = ensure_fingerprint(&song, &app_handle) {
+                app_handle
+                    .state::<Library>()
+                    .report_error(format!("Failed to fingerprint {}: {}", song.id, e));
+            }
+        }
+    });
+}
+
+/// Remove the cached fingerprint for a file hash
+pub fn remove_cached_fingerprint(file_hash: &str, data_dir: &Path) -> Result<(), String> {
+    let cache_path = fingerprint_cache_path(file_hash, data_dir);
+
+    if cache_path.exists() {
+        fs::remove_file(&cache_path)
+            .map_err(|e| format!("Failed to delete fingerprint cache: {}", e))?;
+    }
+
+    Ok(())
+}
+
+/// Find the root of a song's group, compressing the path as we go
+fn find_root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// Group songs that are likely the same recording
+pub fn find_duplicate_songs(
+    threshold: f32,
+    app_handle: &tauri::AppHandle,
+) -> Result<Vec<DuplicateGroup>, String> {
+    let songs = app_handle
+        .state::<Library>()
+        .read(|library| Ok(library.songs.clone()))?;
+
+    // Songs whose audio can't be decoded are left out rather than failing the scan
+    let mut candidates: Vec<(&Song, Fingerprint)> = songs
+        .iter()
+        .filter_map(|song| {
+            ensure_fingerprint(song, app_handle)
+                .ok()
+                .map(|fingerprint| (song, fingerprint))
+        })
+        .collect();
+
+    // Sorting by duration lets each song be compared only with its close neighbours
+    candidates.sort_by_key(|(_, fingerprint)| fingerprint.duration_secs);
+
+    let mut parents: Vec<usize> = (0..candidates.len()).collect();
+    let mut scores: HashMap<(usize, usize), f32> = HashMap::new();
+
+    for i in 0..candidates.len() {
+        for j in i + 1..candidates.len() {
+            let (song_a, print_a) = &candidates[i];
+            let (song_b, print_b) = &candidates[j];
+
+            if print_b.duration_secs - print_a.duration_secs > MAX_DURATION_DIFF_SECS {
+                break;
+            }
+
+            let similarity = if song_a.file_hash.is_some() && song_a.file_hash == song_b.file_hash {
+                1.0
+            } else {
+                compare_fingerprints(print_a, print_b)
+            };
+
+            if similarity >= threshold {
+                scores.insert((i, j), similarity);
+                let root_a = find_root(&mut parents, i);
+                let root_b = find_root(&mut parents, j);
+                parents[root_b] = root_a;
+            }
+        }
+    }
+
+    let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..candidates.len() {
+        let root = find_root(&mut parents, i);
+        members.entry(root).or_default().push(i);
+    }
+
+    let mut groups = Vec::new();
+
+    for indices in members.into_values().filter(|m| m.len() > 1) {
+        // Suggest keeping the highest-quality copy
+        let keep = *indices
+            .iter()
+            .max_by_key(|&&i| (candidates[i].0.bitrate_kbps, candidates[i].0.file_size))
+            .unwrap();
+
+        let matches = indices
+            .iter()
+            .map(|&i| {
+                let key = (i.min(keep), i.max(keep));
+                let similarity = if i == keep {
+                    1.0
+                } else {
+                    scores.get(&key).copied().unwrap_or_else(|| {
+                        compare_fingerprints(&candidates[keep].1, &candidates[i].1)
+                    })
+                };
+
+                DuplicateMatch {
+                    song: candidates[i].0.clone(),
+                    similarity,
+                }
+            })
+            .collect();
+
+        groups.push(DuplicateGroup {
+            keep_id: candidates[keep].0.id.clone(),
+            matches,
+        });
+    }
+
+    // List groups in library order of the song to keep, so repeated scans agree
+    let position = |id: &str| songs.iter().position(|s| s.id == id);
+    groups.sort_by_key(|group| position(&group.keep_id));
+
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A tune of half-second tones whose pitches are picked from `seed`
+    fn tune(seed: u32, secs: usize) -> Vec<f32> {
+        let mut state = seed;
+        let mut samples = Vec::new();
+
+        for _ in 0..secs * 2 {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let freq = 220.0 * 2f32.powf((state >> 24) as f32 % 24.0 / 12.0);
+
+            let len = TARGET_SAMPLE_RATE as usize / 2;
+            samples.extend(
+                (0..len).map(|i| (2.0 * PI * freq * i as f32 / TARGET_SAMPLE_RATE as f32).sin()),
+            );
+        }
+
+        samples
+    }
+
+    fn fingerprint(samples: &[f32]) -> Fingerprint {
+        Fingerprint {
+            duration_secs: (samples.len() / TARGET_SAMPLE_RATE as usize) as u32,
+            hashes: frame_hashes(samples, TARGET_SAMPLE_RATE),
+        }
+    }
+
+    #[test]
+    fn identical_audio_matches_even_with_a_lead_in() {
+        let original = tune(1, 30);
+        let mut delayed = vec![0.0; TARGET_SAMPLE_RATE as usize * 2];
+        delayed.extend(&original);
+
+        let print = fingerprint(&original);
+        assert_eq!(compare_fingerprints(&print, &print), 1.0);
+        assert!(compare_fingerprints(&print, &fingerprint(&delayed)) >= DEFAULT_THRESHOLD);
+    }
+
+    #[test]
+    fn unrelated_audio_does_not_match() {
+        let a = fingerprint(&tune(1, 30));
+        let b = fingerprint(&tune(2, 30));
+
+        assert!(compare_fingerprints(&a, &b) < DEFAULT_THRESHOLD);
+    }
+
+    #[test]
+    fn short_fingerprints_are_not_compared() {
+        let print = fingerprint(&tune(1, 3));
+
+        assert_eq!(compare_fingerprints(&print, &print), 0.0);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::library::Library;
+use crate::models::{AppMetadata, Playlist, PlaylistFolder};
+use std::time::SystemTime;
+use tauri::Manager;
+
+/// Work out a new overall order after moving an item to `index` among its siblings.
+/// Playlists and folders keep a single ordering, so order within a folder is the order
+/// of its items in that list.
+fn reposition(order: &[&str], siblings: &[&str], moved: &str, index: usize) -> Vec<String> {
+    let mut order: Vec<String> = order
+        .iter()
+        .filter(|id| **id != moved)
+        .map(|id| id.to_string())
+        .collect();
+    let siblings: Vec<&str> = siblings.iter().copied().filter(|id| *id != moved).collect();
+
+    let position_of = |id: &str, order: &[String]| order.iter().position(|o| o == id);
+
+    // Go before the sibling now at `index`, or after the last one if moving past the end
+    let at = match siblings.get(index) {
+        Some(next) => position_of(next, &order),
+        None => siblings
+            .last()
+            .and_then(|last| position_of(last, &order))
+            .map(|i| i + 1),
+    }
+    .unwrap_or(order.len());
+
+    order.insert(at, moved.to_string());
+    order
+}
+
+/// Check that a folder exists, if one is given
+fn check_folder(library: &AppMetadata, folder_id: &Option<String>) -> Result<(), String> {
+    if let Some(folder_id) = folder_id {
+        library.get_folder(folder_id)?;
+    }
+
+    Ok(())
+}
+
+/// Check that a folder isn't being moved into itself or one of its own subfolders
+fn check_not_inside(
+    library: &AppMetadata,
+    folder_id: &str,
+    parent_id: &Option<String>,
+) -> Result<(), String> {
+    // Walk up from the new parent; the folder must not be one of its ancestors
+    let mut ancestor = parent_id.clone();
+    while let Some(id) = ancestor {
+        if id == folder_id {
+            return Err("Cannot move a folder into itself".to_string());
+        }
+        ancestor = library.get_folder(&id)?.parent_id.clone();
+    }
+
+    Ok(())
+}
+
+/// Get all playlist folders in display order
+pub fn get_playlist_folders(app_handle: &tauri::AppHandle) -> Result<Vec<PlaylistFolder>, String> {
+    app_handle
+        .state::<Library>()
+        .read(|library| Ok(library.folders.clone()))
+}
+
+/// Create a folder, at the top level or inside another folder
+pub fn create_playlist_folder(
+    name: String,
+    parent_id: Option<String>,
+    app_handle: &tauri::AppHandle,
+) -> Result<PlaylistFolder, String> {
+    use uuid::Uuid;
+
+    app_handle.state::<Library>().write(|library| {
+        check_folder(library, &parent_id)?;
+
+        let folder = PlaylistFolder {
+            id: Uuid::new_v4().to_string(),
+            name,
+            parent_id,
+            created_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        };
+
+        library.save_folder(&folder)?;
+
+        Ok(folder)
+    })
+}
+
+/// Rename a folder
+pub fn rename_playlist_folder(
+    folder_id: String,
+    new_name: String,
+    app_handle: &tauri::AppHandle,
+) -> Result<PlaylistFolder, String> {
+    app_handle.state::<Library>().write(|library| {
+        let mut folder = library.get_folder(&folder_id)?.clone();
+        folder.name = new_name;
+
+        library.save_folder(&folder)?;
+
+        Ok(folder)
+    })
+}
+
+/// Move a folder into another folder (or the top level) at a position among its siblings
+pub fn move_playlist_folder(
+    folder_id: String,
+    parent_id: Option<String>,
+    index: usize,
+    app_handle: &tauri::AppHandle,
+) -> Result<(), String> {
+    app_handle.state::<Library>().write(|library| {
+        let mut folder = library.get_folder(&folder_id)?.clone();
+        check_folder(library, &parent_id)?;
+
+        check_not_inside(library, &folder_id, &parent_id)?;
+
+        let order: Vec<&str> = library.folders.iter().map(|f| f.id.as_str()).collect();
+        let siblings: Vec<&str> = library
+            .folders
+            .iter()
+            .filter(|f| f.parent_id == parent_id)
+            .map(|f| f.id.as_str())
+            .collect();
+        let new_order = reposition(&order, &siblings, &folder_id, index);
+
+        folder.parent_id = parent_id;
+        library.save_folder(&folder)?;
+        library.set_folder_order(&new_order)
+    })
+}
+
+/// Delete a folder. Its playlists and subfolders move up into its parent.
+pub fn delete_playlist_folder(
+    folder_id: String,
+    app_handle: &tauri::AppHandle,
+) -> Result<(), String> {
+    app_handle.state::<Library>().write(|library| {
+        let parent_id = library.get_folder(&folder_id)?.parent_id.clone();
+
+        let subfolders: Vec<PlaylistFolder> = library
+            .folders
+            .iter()
+            .filter(|f| f.parent_id.as_deref() == Some(folder_id.as_str()))
+            .cloned()
+            .collect();
+        for mut subfolder in subfolders {
+            subfolder.parent_id = parent_id.clone();
+            library.save_folder(&subfolder)?;
+        }
+
+        let playlists: Vec<Playlist> = library
+            .playlists
+            .iter()
+            .filter(|p| p.folder_id.as_deref() == Some(folder_id.as_str()))
+            .cloned()
+            .collect();
+        for mut playlist in playlists {
+            playlist.folder_id = parent_id.clone();
+            library.save_playlist(&playlist)?;
+        }
+
+        library.delete_folder(&folder_id)
+    })
+}
+
+/// Move a playlist into a folder (or the top level) at a position among its siblings
+pub fn move_playlist(
+    playlist_id: String,
+    folder_id: Option<String>,
+    index: usize,
+    app_handle: &tauri::AppHandle,
+) -> Result<(), String> {
+    app_handle.state::<Library>().write(|library| {
+        let mut playlist = library.get_playlist(&playlist_id)?.clone();
+        check_folder(library, &folder_id)?;
+
+        let order: Vec<&str> = library.playlists.iter().map(|p| p.id.as_str()).collect();
+        let siblings: Vec<&str> = library
+            .playlists
+            .iter()
+            .filter(|p| p.folder_id == folder_id)
+            .map(|p| p.id.as_str())
+            .collect();
+        let new_order = reposition(&order, &siblings, &playlist_id, index);
+
+        playlist.folder_id = folder_id;
+        library.save_playlist(&playlist)?;
+        library.set_playlist_order(&new_order)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::folder;
+
+    /// Folders a > b > c, and d on its own
+    fn library() -> AppMetadata {
+        AppMetadata {
+            folders: vec![
+                folder("a", None),
+                folder("b", Some("a")),
+                folder("c", Some("b")),
+                folder("d", None),
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn to(id: &str) -> Option<String> {
+        Some(id.to_string())
+    }
+
+    #[test]
+    fn folder_cannot_move_into_itself_or_below() {
+        let library = library();
+
+        assert!(check_not_inside(&library, "a", &to("a")).is_err());
+        assert!(check_not_inside(&library, "a", &to("b")).is_err());
+        assert!(check_not_inside(&library, "a", &to("c")).is_err());
+        assert!(check_not_inside(&library, "b", &to("c")).is_err());
+    }
+
+    #[test]
+    fn folder_can_move_up_or_across() {
+        let library = library();
+
+        assert!(check_not_inside(&library, "c", &None).is_ok());
+        assert!(check_not_inside(&library, "c", &to("a")).is_ok());
+        assert!(check_not_inside(&library, "b", &to("d")).is_ok());
+        assert!(check_not_inside(&library, "d", &to("c")).is_ok());
+    }
+}
@@ -0,0 +1,251 @@
+use crate::filesystem;
+use crate::fingerprint;
+use crate::library::Library;
+use crate::metadata;
+use crate::models::{AppMetadata, DanglingEntry, LibraryReport, RepairCategory, Song};
+use crate::playlist_mirror;
+use crate::song_manager;
+use crate::waveform;
+use std::collections::HashSet;
+use std::path::Path;
+use tauri::Manager;
+
+/// Find MP3s in all_songs that no song refers to
+fn find_orphan_files(library: &AppMetadata, all_songs_dir: &Path) -> Result<Vec<String>, String> {
+    let known: HashSet<&str> = library.songs.iter().map(|s| s.file_path.as_str()).collect();
+
+    let mut orphans = Vec::new();
+    for path in filesystem::scan_directory_for_mp3s(all_songs_dir)? {
+        let relative_path = path
+            .strip_prefix(all_songs_dir)
+            .map_err(|e| format!("Failed to get relative path: {}", e))?
+            .to_str()
+            .ok_or_else(|| "Invalid UTF-8 in path".to_string())?
+            .to_string();
+
+        if !known.contains(relative_path.as_str()) {
+            orphans.push(relative_path);
+        }
+    }
+
+    orphans.sort();
+    Ok(orphans)
+}
+
+/// Find playlist entries that point at songs no longer in the library
+fn find_dangling_entries(library: &AppMetadata) -> Vec<DanglingEntry> {
+    let song_ids: HashSet<&str> = library.songs.iter().map(|s| s.id.as_str()).collect();
+
+    library
+        .playlists
+        .iter()
+        .flat_map(|playlist| {
+            playlist
+                .entries
+                .iter()
+                .filter(|entry| !song_ids.contains(entry.song_id.as_str()))
+                .map(|entry| DanglingEntry {
+                    playlist_id: playlist.id.clone(),
+                    song_id: entry.song_id.clone(),
+                })
+        })
+        .collect()
+}
+
+/// Build a report of every problem in a library snapshot
+fn build_report(library: &AppMetadata, app_data_path: &Path) -> Result<LibraryReport, String> {
+    let all_songs_dir = app_data_path.join("music/all_songs");
+
+    let missing_files = library
+        .songs
+        .iter()
+        .filter(|song| !all_songs_dir.join(&song.file_path).is_file())
+        .cloned()
+        .collect();
+
+    Ok(LibraryReport {
+        orphan_files: find_orphan_files(library, &all_songs_dir)?,
+        missing_files,
+        dangling_entries: find_dangling_entries(library),
+        stale_symlinks: playlist_mirror::find_stale(library, &app_data_path.join("music"))?,
+        background_errors: Vec::new(),
+    })
+}
+
+/// Check the library for drift between the database and the files on disk
+fn check(library: &Library) -> Result<LibraryReport, String> {
+    let mut report = library.read(|state| build_report(state, library.data_dir()))?;
+    report.background_errors = library.background_errors();
+
+    Ok(report)
+}
+
+/// Fix the selected categories of problems. Returns the songs imported from orphaned files.
+fn repair(library: &Library, categories: &[RepairCategory]) -> Result<Vec<Song>, String> {
+    let all_songs_dir = library.data_dir().join("music/all_songs");
+
+    // Read tags outside the lock, since it means reading every orphaned file
+    let mut orphan_songs = Vec::new();
+    if categories.contains(&RepairCategory::OrphanFiles) {
+        for relative_path in check(library)?.orphan_files {
+            let absolute_path = all_songs_dir.join(&relative_path);
+            orphan_songs.push(metadata::extract_song_metadata(
+                &absolute_path,
+                relative_path,
+            )?);
+        }
+    }
+
+    library.take_snapshot()?;
+    let imported = library.write_settled(|library| {
+        // Check again under the lock so nothing changed since is touched
+        let report = build_report(library, library.data_dir())?;
+
+        if categories.contains(&RepairCategory::MissingFiles) {
+            for song in &report.missing_files {
+                for playlist_id in library.playlists_containing(&song.id) {
+                    let mut playlist = library.get_playlist(&playlist_id)?.clone();
+                    playlist.remove_song(&song.id);
+                    library.save_playlist(&playlist)?;
+                }
+                library.delete_song(&song.id)?;
+                song_manager::delete_song_files(song, library)?;
+            }
+        }
+
+        if categories.contains(&RepairCategory::DanglingEntries) {
+            for entry in &report.dangling_entries {
+                let mut playlist = library.get_playlist(&entry.playlist_id)?.clone();
+                playlist.remove_song(&entry.song_id);
+                library.save_playlist(&playlist)?;
+            }
+        }
+
+        let mut imported: Vec<Song> = Vec::new();
+        for song in orphan_songs {
+            if report.orphan_files.contains(&song.file_path) {
+                library.save_song(&song)?;
+                imported.push(song);
+            }
+        }
+
+        Ok(imported)
+    })?;
+
+    // Last, since the fixes above can leave more links stale
+    if categories.contains(&RepairCategory::StaleSymlinks) {
+        library.sync_playlist_folders()?;
+    }
+
+    Ok(imported)
+}
+
+/// Check the library for drift between the database and the files on disk
+pub fn check_library(app_handle: &tauri::AppHandle) -> Result<LibraryReport, String> {
+    check(&app_handle.state::<Library>())
+}
+
+/// Fix the selected categories of problems. Returns what's left afterwards.
+pub fn repair_library(
+    categories: Vec<RepairCategory>,
+    app_handle: &tauri::AppHandle,
+) -> Result<LibraryReport, String> {
+    let library = app_handle.state::<Library>();
+    let imported = repair(&library, &categories)?;
+
+    waveform::generate_in_background(imported.clone(), app_handle.clone());
+    fingerprint::generate_in_background(imported, app_handle.clone());
+
+    check(&library)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{open_library, playlist, song};
+    use std::fs;
+
+    /// Song a is on disk, song b's file is gone, and mix lists both plus a song not in
+    /// the library. An untracked stray.mp3 sits in all_songs.
+    fn setup(data_dir: &Path) -> Library {
+        let all_songs_dir = data_dir.join("music/all_songs");
+        fs::create_dir_all(&all_songs_dir).unwrap();
+        fs::write(all_songs_dir.join("a.mp3"), b"a").unwrap();
+        fs::write(all_songs_dir.join("stray.mp3"), b"stray").unwrap();
+
+        let library = open_library(data_dir);
+        library
+            .write(|library| {
+                library.save_song(&song("a"))?;
+                library.save_song(&song("b"))?;
+                library.save_playlist(&playlist("mix", &["a", "b", "ghost"]))
+            })
+            .unwrap();
+
+        library
+    }
+
+    #[test]
+    fn check_finds_each_kind_of_drift() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = setup(dir.path());
+
+        let report = check(&library).unwrap();
+        assert_eq!(report.orphan_files, ["stray.mp3"]);
+        assert_eq!(report.missing_files.len(), 1);
+        assert_eq!(report.missing_files[0].id, "b");
+        assert_eq!(report.dangling_entries.len(), 1);
+        assert_eq!(report.dangling_entries[0].song_id, "ghost");
+    }
+
+    #[test]
+    fn repair_fixes_only_the_chosen_categories() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = setup(dir.path());
+
+        let imported = repair(
+            &library,
+            &[
+                RepairCategory::MissingFiles,
+                RepairCategory::DanglingEntries,
+            ],
+        )
+        .unwrap();
+        assert!(imported.is_empty());
+
+        library
+            .read(|library| {
+                let song_ids: Vec<&str> = library.songs.iter().map(|s| s.id.as_str()).collect();
+                assert_eq!(song_ids, ["a"]);
+
+                let entries = &library.get_playlist("mix")?.entries;
+                let entry_ids: Vec<&str> = entries.iter().map(|e| e.song_id.as_str()).collect();
+                assert_eq!(entry_ids, ["a"]);
+                Ok(())
+            })
+            .unwrap();
+
+        let report = check(&library).unwrap();
+        assert!(report.missing_files.is_empty());
+        assert!(report.dangling_entries.is_empty());
+        assert_eq!(report.orphan_files, ["stray.mp3"]);
+
+        // The library from before the repair is kept as a snapshot
+        let snapshots = fs::read_dir(dir.path().join("snapshots")).unwrap().count();
+        assert_eq!(snapshots, 1);
+    }
+
+    #[test]
+    fn repair_imports_orphaned_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = setup(dir.path());
+
+        let imported = repair(&library, &[RepairCategory::OrphanFiles]).unwrap();
+        assert_eq!(imported.len(), 1);
+        assert_eq!(imported[0].file_path, "stray.mp3");
+
+        let report = check(&library).unwrap();
+        assert!(report.orphan_files.is_empty());
+        assert_eq!(report.missing_files.len(), 1);
+    }
+}
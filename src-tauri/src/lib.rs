@@ -1,10 +1,40 @@
+mod audio;
+mod backup;
+mod browse;
+mod database;
+mod device_sync;
 mod filesystem;
+mod fingerprint;
+mod folder_manager;
+mod integrity;
+mod library;
 mod metadata;
+mod migrations;
 mod models;
+mod playlist_cover;
 mod playlist_manager;
-
-use models::{Playlist, Song};
+mod playlist_mirror;
+mod search;
+mod search_query;
+mod smart_playlist;
+mod snapshots;
+mod song_manager;
+mod stats;
+#[cfg(test)]
+mod test_fixtures;
+mod trash;
+mod waveform;
+
+use library::Library;
+use models::{
+    Album, AlbumDetail, Artist, ArtistDetail, BackupManifest, DuplicateGroup, LibraryReport,
+    LibraryStats, Playlist, PlaylistEntry, PlaylistFolder, RepairCategory, RestoreMode,
+    RestoreSummary, SmartCriteria, Song, SongMatch, SortKey, SplitMode, SyncOptions, SyncSummary,
+    TrashedSong, Waveform,
+};
+use std::ops::Range;
 use std::path::PathBuf;
+use tauri::{Manager, State};
 
 // ==================== IMPORT COMMANDS ====================
 
@@ -37,6 +67,10 @@ async fn import_files(file_paths: Vec<String>, app_handle: tauri::AppHandle) ->
         imported_songs.push(song);
     }
 
+    // Pre-compute waveforms so the playing page doesn't have to wait on decoding
+    waveform::generate_in_background(imported_songs.clone(), app_handle.clone());
+    fingerprint::generate_in_background(imported_songs.clone(), app_handle.clone());
+
     Ok(imported_songs)
 }
 
@@ -56,6 +90,7 @@ async fn import_folder(
     }
 
     let mut song_ids = Vec::new();
+    let mut imported_songs = Vec::new();
 
     // Import each file
     for mp3_file in mp3_files {
@@ -64,9 +99,13 @@ async fn import_folder(
         let song = metadata::extract_song_metadata(&absolute_path, relative_path)?;
 
         metadata::update_song_in_cache(song.clone(), &app_handle)?;
-        song_ids.push(song.id);
+        song_ids.push(song.id.clone());
+        imported_songs.push(song);
     }
 
+    waveform::generate_in_background(imported_songs.clone(), app_handle.clone());
+    fingerprint::generate_in_background(imported_songs, app_handle.clone());
+
     // Create playlist
     let playlist = playlist_manager::create_playlist(playlist_name, song_ids, &app_handle)?;
 
@@ -76,20 +115,29 @@ async fn import_folder(
 // ==================== SONG QUERY COMMANDS ====================
 
 #[tauri::command]
-async fn get_all_songs(app_handle: tauri::AppHandle) -> Result<Vec<Song>, String> {
-    let metadata_cache = metadata::load_metadata_cache(&app_handle)?;
-    Ok(metadata_cache.songs)
+async fn get_all_songs(
+    sort_by: Option<String>,
+    descending: Option<bool>,
+    sort: Option<Vec<SortKey>>,
+    library: State<'_, Library>,
+) -> Result<Vec<Song>, String> {
+    let mut songs = library.read(|library| Ok(library.songs.clone()))?;
+
+    // `sort` takes several keys; `sort_by` is the single-key form
+    if let Some(keys) = sort {
+        metadata::sort_songs_by(&mut songs, &keys)?;
+    } else if let Some(sort_by) = sort_by {
+        metadata::sort_songs(&mut songs, &sort_by, descending.unwrap_or(false))?;
+    }
+
+    Ok(songs)
 }
 
 #[tauri::command]
 async fn get_song_file_path(song_id: String, app_handle: tauri::AppHandle) -> Result<String, String> {
-    let metadata_cache = metadata::load_metadata_cache(&app_handle)?;
-
-    let song = metadata_cache
-        .songs
-        .iter()
-        .find(|s| s.id == song_id)
-        .ok_or_else(|| format!("Song not found: {}", song_id))?;
+    let song = app_handle
+        .state::<Library>()
+        .read(|library| library.get_song(&song_id).cloned())?;
 
     let absolute_path = filesystem::get_song_absolute_path(&song.file_path, &app_handle)?;
 
@@ -100,10 +148,43 @@ async fn get_song_file_path(song_id: String, app_handle: tauri::AppHandle) -> Re
 }
 
 #[tauri::command]
-async fn search_songs(query: String, app_handle: tauri::AppHandle) -> Result<Vec<Song>, String> {
-    let metadata_cache = metadata::load_metadata_cache(&app_handle)?;
-    let results = metadata::search_songs(&query, &metadata_cache.songs);
-    Ok(results)
+async fn search_songs(
+    query: String,
+    library: State<'_, Library>,
+) -> Result<Vec<SongMatch>, String> {
+    library.read(|library| search::search_songs(&query, &library.songs))
+}
+
+#[tauri::command]
+async fn get_library_stats(app_handle: tauri::AppHandle) -> Result<LibraryStats, String> {
+    tauri::async_runtime::spawn_blocking(move || stats::get_library_stats(&app_handle))
+        .await
+        .map_err(|e| format!("Library stats task failed: {}", e))?
+}
+
+// ==================== BROWSE COMMANDS ====================
+
+#[tauri::command]
+async fn get_albums(app_handle: tauri::AppHandle) -> Result<Vec<Album>, String> {
+    browse::get_albums(&app_handle)
+}
+
+#[tauri::command]
+async fn get_album(album_id: String, app_handle: tauri::AppHandle) -> Result<AlbumDetail, String> {
+    browse::get_album(album_id, &app_handle)
+}
+
+#[tauri::command]
+async fn get_artists(app_handle: tauri::AppHandle) -> Result<Vec<Artist>, String> {
+    browse::get_artists(&app_handle)
+}
+
+#[tauri::command]
+async fn get_artist(
+    artist_id: String,
+    app_handle: tauri::AppHandle,
+) -> Result<ArtistDetail, String> {
+    browse::get_artist(artist_id, &app_handle)
 }
 
 // ==================== PLAYLIST QUERY COMMANDS ====================
@@ -125,45 +206,95 @@ async fn search_playlists(query: String, app_handle: tauri::AppHandle) -> Result
     Ok(results)
 }
 
+#[tauri::command]
+async fn get_playlist_cover(
+    playlist_id: String,
+    app_handle: tauri::AppHandle,
+) -> Result<Option<String>, String> {
+    // Building a mosaic decodes and resizes images
+    tauri::async_runtime::spawn_blocking(move || {
+        playlist_manager::get_playlist_cover(playlist_id, &app_handle)
+    })
+    .await
+    .map_err(|e| format!("Cover task failed: {}", e))?
+}
+
 // ==================== SONG EDITING COMMANDS ====================
 
 #[tauri::command]
 async fn delete_song(song_id: String, app_handle: tauri::AppHandle) -> Result<(), String> {
-    // Load metadata to get the song's file path
-    let mut metadata_cache = metadata::load_metadata_cache(&app_handle)?;
+    song_manager::delete_song(song_id, &app_handle)
+}
 
-    let song = metadata_cache
-        .songs
-        .iter()
-        .find(|s| s.id == song_id)
-        .ok_or_else(|| format!("Song not found: {}", song_id))?;
+#[tauri::command]
+async fn merge_songs(
+    keep_id: String,
+    remove_ids: Vec<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<Song, String> {
+    song_manager::merge_songs(keep_id, remove_ids, &app_handle)
+}
 
-    // Get the absolute path of the song file
-    let absolute_path = filesystem::get_song_absolute_path(&song.file_path, &app_handle)?;
+// ==================== TRASH COMMANDS ====================
 
-    // Delete the actual song file
-    if absolute_path.exists() {
-        std::fs::remove_file(&absolute_path)
-            .map_err(|e| format!("Failed to delete song file: {}", e))?;
-    }
+#[tauri::command]
+async fn list_trash(app_handle: tauri::AppHandle) -> Result<Vec<TrashedSong>, String> {
+    trash::list_trash(&app_handle)
+}
 
-    // Remove song from all playlists
-    let playlists = playlist_manager::get_all_playlists(&app_handle)?;
-    for playlist in playlists {
-        if playlist.song_ids.contains(&song_id) {
-            playlist_manager::remove_songs_from_playlist(
-                playlist.id,
-                vec![song_id.clone()],
-                &app_handle,
-            )?;
-        }
-    }
+#[tauri::command]
+async fn restore_from_trash(
+    song_ids: Vec<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<Song>, String> {
+    trash::restore_from_trash(song_ids, &app_handle)
+}
+
+#[tauri::command]
+async fn empty_trash(app_handle: tauri::AppHandle) -> Result<(), String> {
+    trash::empty_trash(&app_handle)
+}
+
+#[tauri::command]
+async fn get_trash_retention_days(app_handle: tauri::AppHandle) -> Result<u32, String> {
+    trash::get_trash_retention_days(&app_handle)
+}
+
+#[tauri::command]
+async fn set_trash_retention_days(days: u32, app_handle: tauri::AppHandle) -> Result<(), String> {
+    trash::set_trash_retention_days(days, &app_handle)
+}
+
+// ==================== WAVEFORM COMMANDS ====================
+
+#[tauri::command]
+async fn get_waveform(
+    song_id: String,
+    resolution: usize,
+    app_handle: tauri::AppHandle,
+) -> Result<Waveform, String> {
+    // Decoding is CPU-bound, so keep it off the async runtime's worker threads
+    tauri::async_runtime::spawn_blocking(move || {
+        waveform::get_waveform(song_id, resolution, &app_handle)
+    })
+    .await
+    .map_err(|e| format!("Waveform task failed: {}", e))?
+}
 
-    // Remove song from metadata cache
-    metadata_cache.songs.retain(|s| s.id != song_id);
-    metadata::save_metadata_cache(&metadata_cache, &app_handle)?;
+// ==================== DUPLICATE DETECTION COMMANDS ====================
 
-    Ok(())
+#[tauri::command]
+async fn find_duplicate_songs(
+    threshold: Option<f32>,
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<DuplicateGroup>, String> {
+    let threshold = threshold.unwrap_or(fingerprint::DEFAULT_THRESHOLD);
+
+    tauri::async_runtime::spawn_blocking(move || {
+        fingerprint::find_duplicate_songs(threshold, &app_handle)
+    })
+    .await
+    .map_err(|e| format!("Duplicate detection task failed: {}", e))?
 }
 
 // ==================== PLAYLIST EDITING COMMANDS ====================
@@ -178,7 +309,7 @@ async fn add_songs_to_playlist(
     playlist_id: String,
     song_ids: Vec<String>,
     app_handle: tauri::AppHandle,
-) -> Result<(), String> {
+) -> Result<Vec<PlaylistEntry>, String> {
     playlist_manager::add_songs_to_playlist(playlist_id, song_ids, &app_handle)
 }
 
@@ -191,6 +322,15 @@ async fn remove_songs_from_playlist(
     playlist_manager::remove_songs_from_playlist(playlist_id, song_ids, &app_handle)
 }
 
+#[tauri::command]
+async fn remove_playlist_entries(
+    playlist_id: String,
+    entry_ids: Vec<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    playlist_manager::remove_playlist_entries(playlist_id, entry_ids, &app_handle)
+}
+
 #[tauri::command]
 async fn rename_playlist(
     playlist_id: String,
@@ -200,6 +340,37 @@ async fn rename_playlist(
     playlist_manager::rename_playlist(playlist_id, new_name, &app_handle)
 }
 
+#[tauri::command]
+async fn set_playlist_description(
+    playlist_id: String,
+    description: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<Playlist, String> {
+    playlist_manager::set_playlist_description(playlist_id, description, &app_handle)
+}
+
+#[tauri::command]
+async fn set_playlist_cover(
+    playlist_id: String,
+    image_path: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<Playlist, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        playlist_manager::set_playlist_cover(playlist_id, image_path, &app_handle)
+    })
+    .await
+    .map_err(|e| format!("Cover task failed: {}", e))?
+}
+
+#[tauri::command]
+async fn set_playlist_pinned(
+    playlist_id: String,
+    pinned: bool,
+    app_handle: tauri::AppHandle,
+) -> Result<Playlist, String> {
+    playlist_manager::set_playlist_pinned(playlist_id, pinned, &app_handle)
+}
+
 #[tauri::command]
 async fn delete_playlist(id: String, app_handle: tauri::AppHandle) -> Result<(), String> {
     playlist_manager::delete_playlist(id, &app_handle)
@@ -214,6 +385,44 @@ async fn reorder_playlist_songs(
     playlist_manager::reorder_playlist_songs(playlist_id, song_ids, &app_handle)
 }
 
+#[tauri::command]
+async fn reorder_playlist_entries(
+    playlist_id: String,
+    entry_ids: Vec<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    playlist_manager::reorder_playlist_entries(playlist_id, entry_ids, &app_handle)
+}
+
+#[tauri::command]
+async fn insert_songs_at(
+    playlist_id: String,
+    index: usize,
+    song_ids: Vec<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<PlaylistEntry>, String> {
+    playlist_manager::insert_songs_at(playlist_id, index, song_ids, &app_handle)
+}
+
+#[tauri::command]
+async fn move_entries(
+    playlist_id: String,
+    from_range: Range<usize>,
+    to_index: usize,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    playlist_manager::move_entries(playlist_id, from_range, to_index, &app_handle)
+}
+
+#[tauri::command]
+async fn sort_playlist(
+    playlist_id: String,
+    keys: Vec<SortKey>,
+    app_handle: tauri::AppHandle,
+) -> Result<Playlist, String> {
+    playlist_manager::sort_playlist(playlist_id, keys, &app_handle)
+}
+
 #[tauri::command]
 async fn reorder_playlists(
     playlist_ids: Vec<String>,
@@ -222,6 +431,172 @@ async fn reorder_playlists(
     playlist_manager::reorder_playlists(playlist_ids, &app_handle)
 }
 
+#[tauri::command]
+async fn duplicate_playlist(
+    playlist_id: String,
+    app_handle: tauri::AppHandle,
+) -> Result<Playlist, String> {
+    playlist_manager::duplicate_playlist(playlist_id, &app_handle)
+}
+
+#[tauri::command]
+async fn merge_playlists(
+    playlist_ids: Vec<String>,
+    name: String,
+    dedupe: bool,
+    app_handle: tauri::AppHandle,
+) -> Result<Playlist, String> {
+    playlist_manager::merge_playlists(playlist_ids, name, dedupe, &app_handle)
+}
+
+#[tauri::command]
+async fn split_playlist(
+    playlist_id: String,
+    mode: SplitMode,
+    app_handle: tauri::AppHandle,
+) -> Result<Vec<Playlist>, String> {
+    playlist_manager::split_playlist(playlist_id, mode, &app_handle)
+}
+
+#[tauri::command]
+async fn create_smart_playlist(
+    name: String,
+    criteria: SmartCriteria,
+    app_handle: tauri::AppHandle,
+) -> Result<Playlist, String> {
+    playlist_manager::create_smart_playlist(name, criteria, &app_handle)
+}
+
+#[tauri::command]
+async fn update_smart_playlist(
+    playlist_id: String,
+    criteria: SmartCriteria,
+    app_handle: tauri::AppHandle,
+) -> Result<Playlist, String> {
+    playlist_manager::update_smart_playlist(playlist_id, criteria, &app_handle)
+}
+
+// ==================== PLAYLIST FOLDER COMMANDS ====================
+
+#[tauri::command]
+async fn get_playlist_folders(app_handle: tauri::AppHandle) -> Result<Vec<PlaylistFolder>, String> {
+    folder_manager::get_playlist_folders(&app_handle)
+}
+
+#[tauri::command]
+async fn create_playlist_folder(
+    name: String,
+    parent_id: Option<String>,
+    app_handle: tauri::AppHandle,
+) -> Result<PlaylistFolder, String> {
+    folder_manager::create_playlist_folder(name, parent_id, &app_handle)
+}
+
+#[tauri::command]
+async fn rename_playlist_folder(
+    folder_id: String,
+    new_name: String,
+    app_handle: tauri::AppHandle,
+) -> Result<PlaylistFolder, String> {
+    folder_manager::rename_playlist_folder(folder_id, new_name, &app_handle)
+}
+
+#[tauri::command]
+async fn move_playlist_folder(
+    folder_id: String,
+    parent_id: Option<String>,
+    index: usize,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    folder_manager::move_playlist_folder(folder_id, parent_id, index, &app_handle)
+}
+
+#[tauri::command]
+async fn delete_playlist_folder(
+    folder_id: String,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    folder_manager::delete_playlist_folder(folder_id, &app_handle)
+}
+
+#[tauri::command]
+async fn move_playlist(
+    playlist_id: String,
+    folder_id: Option<String>,
+    index: usize,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    folder_manager::move_playlist(playlist_id, folder_id, index, &app_handle)
+}
+
+// ==================== DEVICE SYNC COMMANDS ====================
+
+#[tauri::command]
+async fn sync_to_device(
+    target_dir: String,
+    playlist_ids: Vec<String>,
+    options: Option<SyncOptions>,
+    app_handle: tauri::AppHandle,
+) -> Result<SyncSummary, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        device_sync::sync_to_device(
+            &PathBuf::from(target_dir),
+            playlist_ids,
+            options.unwrap_or_default(),
+            &app_handle,
+        )
+    })
+    .await
+    .map_err(|e| format!("Device sync task failed: {}", e))?
+}
+
+// ==================== BACKUP COMMANDS ====================
+
+#[tauri::command]
+async fn export_library_backup(
+    dest_path: String,
+    include_audio: bool,
+    app_handle: tauri::AppHandle,
+) -> Result<BackupManifest, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        backup::export_library_backup(&PathBuf::from(dest_path), include_audio, &app_handle)
+    })
+    .await
+    .map_err(|e| format!("Backup task failed: {}", e))?
+}
+
+#[tauri::command]
+async fn restore_library_backup(
+    archive_path: String,
+    mode: RestoreMode,
+    app_handle: tauri::AppHandle,
+) -> Result<RestoreSummary, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        backup::restore_library_backup(&PathBuf::from(archive_path), mode, &app_handle)
+    })
+    .await
+    .map_err(|e| format!("Restore task failed: {}", e))?
+}
+
+// ==================== INTEGRITY COMMANDS ====================
+
+#[tauri::command]
+async fn check_library(app_handle: tauri::AppHandle) -> Result<LibraryReport, String> {
+    tauri::async_runtime::spawn_blocking(move || integrity::check_library(&app_handle))
+        .await
+        .map_err(|e| format!("Library check task failed: {}", e))?
+}
+
+#[tauri::command]
+async fn repair_library(
+    categories: Vec<RepairCategory>,
+    app_handle: tauri::AppHandle,
+) -> Result<LibraryReport, String> {
+    tauri::async_runtime::spawn_blocking(move || integrity::repair_library(categories, &app_handle))
+        .await
+        .map_err(|e| format!("Library repair task failed: {}", e))?
+}
+
 // ==================== APP RUNNER ====================
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -233,8 +608,54 @@ pub fn run() {
             // Initialize app data directory on startup
             filesystem::init_app_data_dir(&app.handle())?;
 
-            // Remove any duplicate playlists
-            playlist_manager::deduplicate_playlists(&app.handle())?;
+            // Open the library database, recovering from a snapshot or importing
+            // a legacy metadata.json if needed, and load it into memory
+            let (conn, restored_from) = database::init_database(app.handle())?;
+            let data_dir = filesystem::get_app_data_path(app.handle())?;
+            let library = Library::load(conn, data_dir)?;
+            if let Some(snapshot) = restored_from {
+                library.report_error(format!(
+                    "Library database was corrupt; restored from {}",
+                    snapshot.display()
+                ));
+            }
+            let songs = library.read(|library| Ok(library.songs.clone()))?;
+            app.manage(library);
+
+            // Read technical properties and tags for songs added before they were tracked
+            let app_handle = app.handle().clone();
+            tauri::async_runtime::spawn_blocking(move || {
+                let library = app_handle.state::<Library>();
+                if let Err(e) = metadata::backfill_technical_properties(&app_handle) {
+                    library.report_error(format!("Failed to backfill technical properties: {}", e));
+                }
+                if let Err(e) = metadata::backfill_tags(&app_handle) {
+                    library.report_error(format!("Failed to backfill tags: {}", e));
+                }
+            });
+
+            // Bring the playlist folders up to date, e.g. for smart playlists with
+            // time-based rules or folders left by versions that named them by ID
+            let app_handle = app.handle().clone();
+            tauri::async_runtime::spawn_blocking(move || {
+                let library = app_handle.state::<Library>();
+                if let Err(e) = library.sync_playlist_folders() {
+                    library.report_error(format!("Failed to update playlist folders: {}", e));
+                }
+            });
+
+            // Permanently delete songs that have been in the trash too long
+            let app_handle = app.handle().clone();
+            tauri::async_runtime::spawn_blocking(move || {
+                if let Err(e) = trash::purge_expired(&app_handle) {
+                    app_handle
+                        .state::<Library>()
+                        .report_error(format!("Failed to purge expired trash: {}", e));
+                }
+            });
+
+            // Fingerprint any songs that haven't been analyzed yet
+            fingerprint::generate_in_background(songs, app.handle().clone());
 
             Ok(())
         })
@@ -244,17 +665,55 @@ pub fn run() {
             get_all_songs,
             get_song_file_path,
             search_songs,
+            get_library_stats,
+            get_albums,
+            get_album,
+            get_artists,
+            get_artist,
             delete_song,
+            merge_songs,
+            list_trash,
+            restore_from_trash,
+            empty_trash,
+            get_trash_retention_days,
+            set_trash_retention_days,
+            get_waveform,
+            find_duplicate_songs,
             get_all_playlists,
             get_playlist,
             search_playlists,
+            get_playlist_cover,
             create_playlist,
             add_songs_to_playlist,
             remove_songs_from_playlist,
+            remove_playlist_entries,
             rename_playlist,
+            set_playlist_description,
+            set_playlist_cover,
+            set_playlist_pinned,
             delete_playlist,
             reorder_playlist_songs,
+            reorder_playlist_entries,
+            insert_songs_at,
+            move_entries,
+            sort_playlist,
             reorder_playlists,
+            duplicate_playlist,
+            merge_playlists,
+            split_playlist,
+            create_smart_playlist,
+            update_smart_playlist,
+            get_playlist_folders,
+            create_playlist_folder,
+            rename_playlist_folder,
+            move_playlist_folder,
+            delete_playlist_folder,
+            move_playlist,
+            sync_to_device,
+            export_library_backup,
+            restore_library_backup,
+            check_library,
+            repair_library,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
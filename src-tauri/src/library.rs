@@ -0,0 +1,654 @@
+use crate::database;
+use crate::models::{AppMetadata, Playlist, PlaylistFolder, Settings, Song, TrashedSong};
+use crate::playlist_mirror;
+use crate::smart_playlist;
+use crate::snapshots;
+use rusqlite::{Connection, Transaction};
+use std::collections::HashSet;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, RwLock};
+
+/// The library held in memory, shared through Tauri managed state.
+/// Reads are served from memory; every change goes through `write`, which
+/// applies it to memory and the database together, one change at a time,
+/// then updates the files on disk, including the playlist folders in music/playlists.
+/// Locks are always taken in the order writer, files, state.
+pub struct Library {
+    state: RwLock<AppMetadata>,
+    writer: Mutex<Connection>,
+    files: Mutex<()>, // Held while a change updates the files on disk
+    data_dir: PathBuf,
+    background_errors: Mutex<Vec<String>>, // Failures with no caller to return them to
+}
+
+/// How many background errors to keep for `check_library`; older ones are dropped
+const KEEP_BACKGROUND_ERRORS: usize = 100;
+
+/// Load the library from the database
+fn load_state(conn: &Connection) -> Result<AppMetadata, String> {
+    Ok(AppMetadata {
+        songs: database::load_songs(conn)?,
+        playlists: database::load_playlists(conn)?,
+        folders: database::load_folders(conn)?,
+        trash: database::load_trash(conn)?,
+        settings: database::load_settings(conn)?,
+    })
+}
+
+impl Library {
+    /// Load the library into memory, keeping `conn` as the only writer. `data_dir` is the
+    /// app data directory, whose music folder holds all_songs and the playlist folders
+    /// mirrored from the library.
+    pub fn load(conn: Connection, data_dir: PathBuf) -> Result<Self, String> {
+        let state = load_state(&conn)?;
+
+        Ok(Library {
+            state: RwLock::new(state),
+            writer: Mutex::new(conn),
+            files: Mutex::new(()),
+            data_dir,
+            background_errors: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Save a snapshot of the library as it is now, before a change that's hard to undo
+    pub fn take_snapshot(&self) -> Result<(), String> {
+        let conn = self
+            .writer
+            .lock()
+            .map_err(|_| "Library writer is unavailable".to_string())?;
+
+        snapshots::take_snapshot(&conn, &self.data_dir)
+    }
+
+    /// Read from the in-memory library
+    pub fn read<T, F>(&self, f: F) -> Result<T, String>
+    where
+        F: FnOnce(&AppMetadata) -> Result<T, String>,
+    {
+        let state = self
+            .state
+            .read()
+            .map_err(|_| "Library state is unavailable".to_string())?;
+
+        f(&state)
+    }
+
+    /// Apply a change to the library. Changes are serialized, so each one sees the
+    /// result of the last. If `f` fails, nothing it did is saved; file changes it
+    /// queued with `Writer::after_commit` only run once the change is saved.
+    /// The next change can start while they run, so files on disk may not have caught
+    /// up with the library yet; use `write_settled` for changes that look at them.
+    pub fn write<T, F>(&self, f: F) -> Result<T, String>
+    where
+        F: FnOnce(&mut Writer) -> Result<T, String>,
+    {
+        self.write_inner(f, false)
+    }
+
+    /// Apply a change like `write`, once earlier changes have finished with the files on disk
+    pub fn write_settled<T, F>(&self, f: F) -> Result<T, String>
+    where
+        F: FnOnce(&mut Writer) -> Result<T, String>,
+    {
+        self.write_inner(f, true)
+    }
+
+    fn write_inner<T, F>(&self, f: F, settled: bool) -> Result<T, String>
+    where
+        F: FnOnce(&mut Writer) -> Result<T, String>,
+    {
+        let mut conn = self
+            .writer
+            .lock()
+            .map_err(|_| "Library writer is unavailable".to_string())?;
+        let files = if settled {
+            Some(self.lock_files()?)
+        } else {
+            None
+        };
+        let mut state = self
+            .state
+            .write()
+            .map_err(|_| "Library state is unavailable".to_string())?;
+
+        // Playlists sharing a name are numbered in display order, so a change to one
+        // playlist can rename another's folder
+        let dir_names = playlist_mirror::dir_names(&state);
+
+        let tx = database::begin(&mut conn)?;
+        let mut writer = Writer {
+            state: &mut state,
+            tx: &tx,
+            data_dir: &self.data_dir,
+            changed_playlists: HashSet::new(),
+            after_commit: Vec::new(),
+        };
+
+        let outcome = f(&mut writer);
+        let mut changed_playlists = writer.changed_playlists;
+        let after_commit = writer.after_commit;
+
+        let value = match outcome.and_then(|value| database::commit(tx).map(|_| value)) {
+            Ok(value) => value,
+            Err(e) => {
+                // The transaction rolled back, so bring memory back in line with the database
+                *state = load_state(&conn)?;
+                return Err(e);
+            }
+        };
+
+        for (id, name) in playlist_mirror::dir_names(&state) {
+            if dir_names.get(&id) != Some(&name) {
+                changed_playlists.insert(id);
+            }
+        }
+
+        // Let reads through while the disk catches up, then let the next change start.
+        // Taking the files lock before the writer is released keeps file changes in the
+        // order the changes were made.
+        drop(state);
+        let _files = match files {
+            Some(files) => files,
+            None => self.lock_files()?,
+        };
+        drop(conn);
+
+        // The change is saved, so a failure from here on doesn't undo it;
+        // check_library reports whatever is left out of date
+        for action in after_commit {
+            if let Err(e) = action() {
+                self.report_error(e);
+            }
+        }
+
+        if !changed_playlists.is_empty() {
+            let state = self
+                .state
+                .read()
+                .map_err(|_| "Library state is unavailable".to_string())?;
+            let scope = Some(&changed_playlists);
+            if let Err(e) = playlist_mirror::sync(&state, &self.music_dir(), scope) {
+                self.report_error(format!("Failed to update playlist folders: {}", e));
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// The app data directory the library's files live in
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    fn lock_files(&self) -> Result<MutexGuard<'_, ()>, String> {
+        self.files
+            .lock()
+            .map_err(|_| "Library files are unavailable".to_string())
+    }
+
+    fn music_dir(&self) -> PathBuf {
+        self.data_dir.join("music")
+    }
+
+    /// Record a failure in work running in the background, such as fingerprinting,
+    /// so `check_library` can report it
+    pub fn report_error(&self, message: String) {
+        if let Ok(mut errors) = self.background_errors.lock() {
+            errors.push(message);
+            let excess = errors.len().saturating_sub(KEEP_BACKGROUND_ERRORS);
+            errors.drain(..excess);
+        }
+    }
+
+    /// Background failures since startup, oldest first
+    pub fn background_errors(&self) -> Vec<String> {
+        self.background_errors
+            .lock()
+            .map(|errors| errors.clone())
+            .unwrap_or_default()
+    }
+
+    /// Rebuild every playlist folder from the library
+    pub fn sync_playlist_folders(&self) -> Result<(), String> {
+        // Hold the writer so no change lands halfway through
+        let _conn = self
+            .writer
+            .lock()
+            .map_err(|_| "Library writer is unavailable".to_string())?;
+        let _files = self.lock_files()?;
+        let state = self
+            .state
+            .read()
+            .map_err(|_| "Library state is unavailable".to_string())?;
+
+        playlist_mirror::sync(&state, &self.music_dir(), None)
+    }
+}
+
+/// Access to the library during a `Library::write`.
+/// Derefs to the in-memory library for reads; changes go through its methods.
+pub struct Writer<'a> {
+    state: &'a mut AppMetadata,
+    tx: &'a Transaction<'a>,
+    data_dir: &'a Path,
+    changed_playlists: HashSet<String>, // Whose folders need updating afterwards
+    after_commit: Vec<Box<dyn FnOnce() -> Result<(), String>>>,
+}
+
+impl Deref for Writer<'_> {
+    type Target = AppMetadata;
+
+    fn deref(&self) -> &AppMetadata {
+        self.state
+    }
+}
+
+impl Writer<'_> {
+    /// The app data directory the library's files live in
+    pub fn data_dir(&self) -> &Path {
+        self.data_dir
+    }
+
+    /// Run `action` once the change is saved, outside the library lock. For changes to
+    /// files that can't be rolled back, such as deleting them, so a failed change
+    /// leaves the files alone.
+    pub fn after_commit<F>(&mut self, action: F)
+    where
+        F: FnOnce() -> Result<(), String> + 'static,
+    {
+        self.after_commit.push(Box::new(action));
+    }
+
+    /// Note the playlists whose folders a change to a song affects. `old` is None for a
+    /// new song and `new` is None for a deleted one.
+    fn song_changed(&mut self, song_id: &str, old: Option<&Song>, new: Option<&Song>) {
+        // Links are named after the artist and title and point at the file
+        let affects_links = match (old, new) {
+            (Some(old), Some(new)) => {
+                old.artist != new.artist || old.title != new.title || old.file_path != new.file_path
+            }
+            _ => true,
+        };
+
+        for playlist in &self.state.playlists {
+            let changed = match &playlist.smart {
+                Some(criteria) => smart_playlist::affected_by(criteria, old, new, affects_links),
+                None => affects_links && playlist.contains_song(song_id),
+            };
+
+            if changed {
+                self.changed_playlists.insert(playlist.id.clone());
+            }
+        }
+    }
+
+    /// Insert a song, or update it if it already exists
+    pub fn save_song(&mut self, song: &Song) -> Result<(), String> {
+        database::save_song(self.tx, song)?;
+
+        let old = self.state.find_song(&song.id).cloned();
+        self.song_changed(&song.id, old.as_ref(), Some(song));
+
+        match self.state.songs.iter_mut().find(|s| s.id == song.id) {
+            Some(existing) => *existing = song.clone(),
+            None => self.state.songs.push(song.clone()),
+        }
+
+        Ok(())
+    }
+
+    /// Delete a song (playlist entries are handled separately)
+    pub fn delete_song(&mut self, song_id: &str) -> Result<(), String> {
+        database::delete_song(self.tx, song_id)?;
+
+        let old = self.state.find_song(song_id).cloned();
+        self.song_changed(song_id, old.as_ref(), None);
+        self.state.songs.retain(|s| s.id != song_id);
+
+        Ok(())
+    }
+
+    /// Insert a playlist at the end of the list, or update it if it already exists
+    pub fn save_playlist(&mut self, playlist: &Playlist) -> Result<(), String> {
+        database::save_playlist(self.tx, playlist)?;
+        self.changed_playlists.insert(playlist.id.clone());
+
+        match self
+            .state
+            .playlists
+            .iter_mut()
+            .find(|p| p.id == playlist.id)
+        {
+            Some(existing) => *existing = playlist.clone(),
+            None => self.state.playlists.push(playlist.clone()),
+        }
+
+        Ok(())
+    }
+
+    /// Delete a playlist and its entries
+    pub fn delete_playlist(&mut self, playlist_id: &str) -> Result<(), String> {
+        database::delete_playlist(self.tx, playlist_id)?;
+        self.changed_playlists.insert(playlist_id.to_string());
+        self.state.playlists.retain(|p| p.id != playlist_id);
+
+        Ok(())
+    }
+
+    /// Set the display order of playlists. Every playlist must be listed.
+    pub fn set_playlist_order(&mut self, playlist_ids: &[String]) -> Result<(), String> {
+        if playlist_ids.len() != self.state.playlists.len() {
+            return Err("Playlist order must list every playlist".to_string());
+        }
+
+        database::set_playlist_order(self.tx, playlist_ids)?;
+
+        let mut remaining = std::mem::take(&mut self.state.playlists);
+        for id in playlist_ids {
+            let index = remaining
+                .iter()
+                .position(|p| p.id == *id)
+                .ok_or_else(|| format!("Playlist not found: {}", id))?;
+            self.state.playlists.push(remaining.remove(index));
+        }
+
+        Ok(())
+    }
+
+    /// Insert a playlist folder at the end of the list, or update it if it already exists
+    pub fn save_folder(&mut self, folder: &PlaylistFolder) -> Result<(), String> {
+        database::save_folder(self.tx, folder)?;
+
+        match self.state.folders.iter_mut().find(|f| f.id == folder.id) {
+            Some(existing) => *existing = folder.clone(),
+            None => self.state.folders.push(folder.clone()),
+        }
+
+        Ok(())
+    }
+
+    /// Delete a playlist folder (its contents are handled separately)
+    pub fn delete_folder(&mut self, folder_id: &str) -> Result<(), String> {
+        database::delete_folder(self.tx, folder_id)?;
+        self.state.folders.retain(|f| f.id != folder_id);
+
+        Ok(())
+    }
+
+    /// Set the display order of playlist folders. Every folder must be listed.
+    pub fn set_folder_order(&mut self, folder_ids: &[String]) -> Result<(), String> {
+        if folder_ids.len() != self.state.folders.len() {
+            return Err("Folder order must list every folder".to_string());
+        }
+
+        database::set_folder_order(self.tx, folder_ids)?;
+
+        let mut remaining = std::mem::take(&mut self.state.folders);
+        for id in folder_ids {
+            let index = remaining
+                .iter()
+                .position(|f| f.id == *id)
+                .ok_or_else(|| format!("Folder not found: {}", id))?;
+            self.state.folders.push(remaining.remove(index));
+        }
+
+        Ok(())
+    }
+
+    /// Add a song to the trash
+    pub fn save_trashed_song(&mut self, trashed: &TrashedSong) -> Result<(), String> {
+        database::save_trashed_song(self.tx, trashed)?;
+
+        self.state.trash.retain(|t| t.song.id != trashed.song.id);
+        self.state.trash.push(trashed.clone());
+
+        Ok(())
+    }
+
+    /// Remove a song from the trash
+    pub fn delete_trashed_song(&mut self, song_id: &str) -> Result<(), String> {
+        database::delete_trashed_song(self.tx, song_id)?;
+        self.state.trash.retain(|t| t.song.id != song_id);
+
+        Ok(())
+    }
+
+    /// Replace the app settings
+    pub fn save_settings(&mut self, settings: &Settings) -> Result<(), String> {
+        database::save_settings(self.tx, settings)?;
+        self.state.settings = settings.clone();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::PlaylistEntry;
+    use crate::test_fixtures::{open_database, open_library, playlist, song};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Add a song and append it to the playlist in one change
+    fn add_to_playlist(library: &Library, song_id: &str) -> Result<(), String> {
+        library.write(|library| {
+            library.save_song(&song(song_id))?;
+
+            let mut playlist = library.get_playlist("mix")?.clone();
+            playlist.entries.push(PlaylistEntry::new(song_id));
+            library.save_playlist(&playlist)
+        })
+    }
+
+    #[test]
+    fn concurrent_writes_are_all_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = open_library(dir.path());
+        library
+            .write(|library| library.save_playlist(&playlist("mix", &[])))
+            .unwrap();
+
+        let ids: Vec<String> = (0..16).map(|i| format!("song-{}", i)).collect();
+        thread::scope(|scope| {
+            for id in &ids {
+                let library = &library;
+                scope.spawn(move || add_to_playlist(library, id).unwrap());
+            }
+        });
+
+        let check = |songs: &[Song], playlists: &[Playlist]| {
+            assert_eq!(songs.len(), ids.len());
+            let entries = &playlists[0].entries;
+            assert_eq!(entries.len(), ids.len());
+            for id in &ids {
+                assert!(songs.iter().any(|s| s.id == *id));
+                assert!(entries.iter().any(|e| e.song_id == *id));
+            }
+        };
+
+        library
+            .read(|library| {
+                check(&library.songs, &library.playlists);
+                Ok(())
+            })
+            .unwrap();
+
+        let conn = open_database(dir.path());
+        check(
+            &database::load_songs(&conn).unwrap(),
+            &database::load_playlists(&conn).unwrap(),
+        );
+    }
+
+    #[test]
+    fn failed_write_changes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = open_library(dir.path());
+        library
+            .write(|library| library.save_playlist(&playlist("mix", &[])))
+            .unwrap();
+        add_to_playlist(&library, "kept").unwrap();
+
+        let ran = Arc::new(AtomicUsize::new(0));
+        let counter = ran.clone();
+        let result: Result<(), String> = library.write(|library| {
+            library.save_song(&song("lost"))?;
+            library.delete_song("kept")?;
+            let mut playlist = library.get_playlist("mix")?.clone();
+            playlist.entries.push(PlaylistEntry::new("lost"));
+            library.save_playlist(&playlist)?;
+            library.after_commit(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            });
+
+            Err("Something went wrong".to_string())
+        });
+        assert_eq!(result.unwrap_err(), "Something went wrong");
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+
+        let check = |songs: &[Song], playlists: &[Playlist]| {
+            let song_ids: Vec<&str> = songs.iter().map(|s| s.id.as_str()).collect();
+            assert_eq!(song_ids, ["kept"]);
+            let entries = &playlists[0].entries;
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].song_id, "kept");
+        };
+
+        library
+            .read(|library| {
+                check(&library.songs, &library.playlists);
+                Ok(())
+            })
+            .unwrap();
+
+        let conn = open_database(dir.path());
+        check(
+            &database::load_songs(&conn).unwrap(),
+            &database::load_playlists(&conn).unwrap(),
+        );
+    }
+
+    #[test]
+    fn after_commit_runs_once_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = open_library(dir.path());
+
+        let ran = Arc::new(AtomicUsize::new(0));
+        let counter = ran.clone();
+        library
+            .write(|library| {
+                library.after_commit(move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Err("Failed to delete song file".to_string())
+                });
+                library.save_song(&song("a"))
+            })
+            .unwrap();
+
+        // A failed action is reported, but doesn't undo the change
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+        assert_eq!(library.background_errors(), ["Failed to delete song file"]);
+        assert_eq!(
+            database::load_songs(&open_database(dir.path()))
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn next_change_is_saved_while_files_catch_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = open_library(dir.path());
+
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let (first, second) = (order.clone(), order.clone());
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                library
+                    .write(move |library| {
+                        library.after_commit(move || {
+                            started_tx.send(()).unwrap();
+                            release_rx.recv().unwrap();
+                            first.lock().unwrap().push("first");
+                            Ok(())
+                        });
+                        library.save_song(&song("a"))
+                    })
+                    .unwrap();
+            });
+            started_rx.recv().unwrap();
+
+            scope.spawn(|| {
+                library
+                    .write(move |library| {
+                        library.after_commit(move || {
+                            second.lock().unwrap().push("second");
+                            Ok(())
+                        });
+                        library.save_song(&song("b"))
+                    })
+                    .unwrap();
+            });
+
+            // The first change's file work is stuck, but the second change still lands
+            let saved = (0..500).any(|_| {
+                thread::sleep(Duration::from_millis(10));
+                library
+                    .read(|library| Ok(library.find_song("b").is_some()))
+                    .unwrap()
+            });
+            assert!(saved);
+            assert!(order.lock().unwrap().is_empty());
+
+            release_tx.send(()).unwrap();
+        });
+
+        // File work still runs in the order the changes were made
+        assert_eq!(*order.lock().unwrap(), ["first", "second"]);
+    }
+
+    #[test]
+    fn playlist_order_must_list_every_playlist() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = open_library(dir.path());
+        library
+            .write(|library| {
+                for id in ["a", "b", "c"] {
+                    library.save_playlist(&playlist(id, &[]))?;
+                }
+                Ok(())
+            })
+            .unwrap();
+
+        let order = |ids: &[&str]| -> Vec<String> { ids.iter().map(|s| s.to_string()).collect() };
+        assert!(library
+            .write(|library| library.set_playlist_order(&order(&["c", "a"])))
+            .is_err());
+        assert!(library
+            .write(|library| library.set_playlist_order(&order(&["c", "a", "a"])))
+            .is_err());
+        library
+            .write(|library| library.set_playlist_order(&order(&["c", "a", "b"])))
+            .unwrap();
+
+        let ids = |playlists: &[Playlist]| -> Vec<String> {
+            playlists.iter().map(|p| p.id.clone()).collect()
+        };
+        let saved = database::load_playlists(&open_database(dir.path())).unwrap();
+        assert_eq!(ids(&saved), ["c", "a", "b"]);
+        let loaded = library
+            .read(|library| Ok(library.playlists.clone()))
+            .unwrap();
+        assert_eq!(ids(&loaded), ["c", "a", "b"]);
+    }
+}
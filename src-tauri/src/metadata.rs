@@ -1,14 +1,30 @@
-use crate::models::{AppMetadata, Song};
+use crate::audio::{self, StreamInfo};
+use crate::library::Library;
+use crate::models::{PlaylistEntry, Song, SortKey};
 use base64::{engine::general_purpose, Engine as _};
 use id3::{Tag, TagLike};
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fs;
+use std::io::Cursor;
 use std::path::Path;
+use std::time::SystemTime;
+use tauri::Manager;
 use uuid::Uuid;
 
+/// Version of the tag fields read into songs. Songs with an older version have their tags
+/// read again at startup, so new fields get filled in for songs imported before them.
+/// 1: genre and year
+/// 2: track, disc and album artist
+pub const TAGS_VERSION: u32 = 2;
+
 /// Extract song metadata from an MP3 file
 pub fn extract_song_metadata(file_path: &Path, relative_path: String) -> Result<Song, String> {
+    // Read the file once for the tags, the hash and the stream properties
+    let bytes = fs::read(file_path).map_err(|e| format!("Failed to read audio file: {}", e))?;
+
     // Try to read ID3 tags
-    let tag = Tag::read_from_path(file_path).ok();
+    let tag = Tag::read_from2(Cursor::new(&bytes)).ok();
 
     // Extract title (fallback to filename)
     let title = tag
@@ -37,20 +53,196 @@ pub fn extract_song_metadata(file_path: &Path, relative_path: String) -> Result<
     // Extract duration
     let duration_secs = tag.as_ref().and_then(|t| t.duration());
 
+    // Extract genre and year
+    let (genre, year) = tag.as_ref().map(read_genre_and_year).unwrap_or_default();
+
+    // Extract track and disc numbers
+    let track_number = tag.as_ref().and_then(|t| t.track());
+    let disc_number = tag.as_ref().and_then(|t| t.disc());
+
+    // Extract album artist, which compilations set to e.g. "Various Artists"
+    let album_artist = tag.as_ref().and_then(read_album_artist);
+
     // Extract album art
     let album_art = tag.as_ref().and_then(|t| extract_album_art(t));
 
-    Ok(Song {
+    // Hash the file contents so caches can be keyed independently of the song ID
+    let file_hash = Some(crate::filesystem::hash_bytes(&bytes));
+
+    let mut song = Song {
         id: Uuid::new_v4().to_string(),
         title,
         artist,
         album,
         file_path: relative_path,
         duration_secs,
+        genre,
+        year,
         album_art,
+        file_hash,
+        codec: None,
+        bitrate_kbps: None,
+        sample_rate: None,
+        channel_mode: None,
+        is_vbr: None,
+        encoder: None,
+        file_size: None,
+        added_at: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        track_number,
+        disc_number,
+        album_artist,
+        tags_version: TAGS_VERSION,
+    };
+
+    // Read technical properties from the audio stream itself
+    let stream_info = audio::read_stream_info(&bytes).ok();
+    apply_technical_properties(&mut song, stream_info, Some(bytes.len() as u64));
+
+    Ok(song)
+}
+
+/// Copy technical stream properties onto a song
+fn apply_technical_properties(
+    song: &mut Song,
+    stream_info: Option<StreamInfo>,
+    file_size: Option<u64>,
+) {
+    if let Some(info) = stream_info {
+        song.codec = Some(info.codec);
+        song.bitrate_kbps = Some(info.bitrate_kbps);
+        song.sample_rate = Some(info.sample_rate);
+        song.channel_mode = Some(info.channel_mode);
+        song.is_vbr = Some(info.is_vbr);
+        song.encoder = info.encoder;
+
+        // Tags often lack a length, so fall back to the one from the frame headers
+        if song.duration_secs.is_none() {
+            song.duration_secs = Some(info.duration_secs);
+        }
+    }
+
+    song.file_size = file_size;
+}
+
+/// Read the genre and release year from an ID3 tag
+fn read_genre_and_year(tag: &Tag) -> (Option<String>, Option<i32>) {
+    // genre_parsed resolves ID3v1-style numeric genres like "(17)" to their names
+    let genre = tag
+        .genre_parsed()
+        .map(|g| g.trim().to_string())
+        .filter(|g| !g.is_empty());
+
+    // ID3v2.3 stores the year in TYER, ID3v2.4 in TDRC
+    let year = tag.year().or_else(|| tag.date_recorded().map(|d| d.year));
+
+    (genre, year)
+}
+
+/// Fill in technical properties for songs imported before they were recorded
+pub fn backfill_technical_properties(app_handle: &tauri::AppHandle) -> Result<(), String> {
+    let library = app_handle.state::<Library>();
+    let songs = library.read(|library| Ok(library.songs.clone()))?;
+
+    // Read the files first, since this is the slow part
+    let mut updates = Vec::new();
+    for song in songs.iter().filter(|s| s.file_size.is_none()) {
+        let absolute_path =
+            match crate::filesystem::get_song_absolute_path(&song.file_path, app_handle) {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+
+        let bytes = match fs::read(&absolute_path) {
+            Ok(bytes) => bytes,
+            Err(_) => continue,
+        };
+
+        let stream_info = audio::read_stream_info(&bytes).ok();
+        updates.push((song.id.clone(), stream_info, Some(bytes.len() as u64)));
+    }
+
+    if updates.is_empty() {
+        return Ok(());
+    }
+
+    // Re-read each song so changes made while scanning aren't overwritten
+    library.write(|library| {
+        for (song_id, stream_info, file_size) in updates {
+            if let Some(mut song) = library.find_song(&song_id).cloned() {
+                apply_technical_properties(&mut song, stream_info, file_size);
+                library.save_song(&song)?;
+            }
+        }
+
+        Ok(())
     })
 }
 
+/// Read tag fields added since songs were imported, for songs behind `TAGS_VERSION`.
+/// Only fields newer than a song's version are filled in, and each song is only read once,
+/// even if its tags turn out to be empty.
+pub fn backfill_tags(app_handle: &tauri::AppHandle) -> Result<(), String> {
+    let library = app_handle.state::<Library>();
+    let songs = library.read(|library| Ok(library.songs.clone()))?;
+
+    let mut updates = Vec::new();
+    for song in songs.iter().filter(|s| s.tags_version < TAGS_VERSION) {
+        let absolute_path =
+            match crate::filesystem::get_song_absolute_path(&song.file_path, app_handle) {
+                Ok(path) if path.is_file() => path,
+                _ => continue,
+            };
+
+        let tag = Tag::read_from_path(&absolute_path).ok();
+        let genre_and_year = tag.as_ref().map(read_genre_and_year).unwrap_or_default();
+        let track_info = (
+            tag.as_ref().and_then(|t| t.track()),
+            tag.as_ref().and_then(|t| t.disc()),
+            tag.as_ref().and_then(read_album_artist),
+        );
+        updates.push((
+            song.id.clone(),
+            song.tags_version,
+            genre_and_year,
+            track_info,
+        ));
+    }
+
+    if updates.is_empty() {
+        return Ok(());
+    }
+
+    library.write(|library| {
+        for (song_id, tags_version, genre_and_year, track_info) in updates {
+            let Some(mut song) = library.find_song(&song_id).cloned() else {
+                continue;
+            };
+
+            if tags_version < 1 {
+                (song.genre, song.year) = genre_and_year;
+            }
+            if tags_version < 2 {
+                (song.track_number, song.disc_number, song.album_artist) = track_info;
+            }
+
+            song.tags_version = TAGS_VERSION;
+            library.save_song(&song)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Read the album artist (TPE2) from an ID3 tag
+fn read_album_artist(tag: &Tag) -> Option<String> {
+    tag.album_artist()
+        .map(|a| a.trim().to_string())
+        .filter(|a| !a.is_empty())
+}
+
 /// Extract album art from ID3 tag and encode as base64
 fn extract_album_art(tag: &Tag) -> Option<String> {
     // Get the first picture (usually the cover art)
@@ -66,69 +258,157 @@ fn extract_album_art(tag: &Tag) -> Option<String> {
     })
 }
 
-/// Load metadata cache from disk
-pub fn load_metadata_cache(app_handle: &tauri::AppHandle) -> Result<AppMetadata, String> {
-    let app_data_path = crate::filesystem::get_app_data_path(app_handle)?;
-    let metadata_path = app_data_path.join("metadata.json");
+/// Insert or update a single song in the library
+pub fn update_song_in_cache(song: Song, app_handle: &tauri::AppHandle) -> Result<(), String> {
+    app_handle
+        .state::<Library>()
+        .write(|library| library.save_song(&song))
+}
 
-    if !metadata_path.exists() {
-        return Ok(AppMetadata::new());
+/// Get the file hash for a song, computing and saving it if missing
+pub fn ensure_file_hash(song: &Song, app_handle: &tauri::AppHandle) -> Result<String, String> {
+    if let Some(file_hash) = &song.file_hash {
+        return Ok(file_hash.clone());
     }
 
-    let contents = fs::read_to_string(&metadata_path)
-        .map_err(|e| format!("Failed to read metadata file: {}", e))?;
+    let absolute_path = crate::filesystem::get_song_absolute_path(&song.file_path, app_handle)?;
+    let file_hash = crate::filesystem::hash_file(&absolute_path)?;
 
-    serde_json::from_str(&contents)
-        .map_err(|e| format!("Failed to parse metadata JSON: {}", e))
+    // Update the stored song rather than the caller's copy, which may be stale
+    app_handle.state::<Library>().write(|library| {
+        let mut updated = library.get_song(&song.id)?.clone();
+        updated.file_hash = Some(file_hash.clone());
+        library.save_song(&updated)
+    })?;
+
+    Ok(file_hash)
 }
 
-/// Save metadata cache to disk
-pub fn save_metadata_cache(
-    metadata: &AppMetadata,
-    app_handle: &tauri::AppHandle,
-) -> Result<(), String> {
-    let app_data_path = crate::filesystem::get_app_data_path(app_handle)?;
-    let metadata_path = app_data_path.join("metadata.json");
+/// How one sort key compares songs
+enum SongOrder {
+    Field(fn(&Song, &Song) -> Ordering),
+    Random,
+}
+
+/// Look up how to compare songs by a field name (e.g. "title", "bitrate", "file_size")
+fn song_order(field: &str) -> Result<SongOrder, String> {
+    let compare: fn(&Song, &Song) -> Ordering = match field {
+        "title" => |a, b| a.title.to_lowercase().cmp(&b.title.to_lowercase()),
+        "artist" => |a, b| a.artist.to_lowercase().cmp(&b.artist.to_lowercase()),
+        "album" => |a, b| a.album.to_lowercase().cmp(&b.album.to_lowercase()),
+        // Disc first, so multi-disc albums play through in order
+        "track" => |a, b| (a.disc_number, a.track_number).cmp(&(b.disc_number, b.track_number)),
+        "duration" => |a, b| a.duration_secs.cmp(&b.duration_secs),
+        "codec" => |a, b| a.codec.cmp(&b.codec),
+        "bitrate" => |a, b| a.bitrate_kbps.cmp(&b.bitrate_kbps),
+        "sample_rate" => |a, b| a.sample_rate.cmp(&b.sample_rate),
+        "channel_mode" => |a, b| a.channel_mode.cmp(&b.channel_mode),
+        "vbr" => |a, b| a.is_vbr.cmp(&b.is_vbr),
+        "encoder" => |a, b| a.encoder.cmp(&b.encoder),
+        "file_size" => |a, b| a.file_size.cmp(&b.file_size),
+        "genre" => |a, b| {
+            let genre = |s: &Song| s.genre.as_ref().map(|g| g.to_lowercase());
+            genre(a).cmp(&genre(b))
+        },
+        "year" => |a, b| a.year.cmp(&b.year),
+        "added_at" => |a, b| a.added_at.cmp(&b.added_at),
+        "random" => return Ok(SongOrder::Random),
+        _ => return Err(format!("Unknown sort field: {}", field)),
+    };
 
-    let json = serde_json::to_string_pretty(metadata)
-        .map_err(|e| format!("Failed to serialize metadata: {}", e))?;
+    Ok(SongOrder::Field(compare))
+}
 
-    fs::write(&metadata_path, json)
-        .map_err(|e| format!("Failed to write metadata file: {}", e))?;
+/// Work out the sorted order of a list of songs, comparing by each key in turn, as
+/// indexes into the list. Missing songs go last. Sorting is stable, so songs that
+/// compare equal on every key keep their order.
+fn sorted_order(songs: &[Option<&Song>], keys: &[SortKey]) -> Result<Vec<usize>, String> {
+    let orders = keys
+        .iter()
+        .map(|key| Ok((song_order(&key.field)?, key.descending)))
+        .collect::<Result<Vec<(SongOrder, bool)>, String>>()?;
+
+    // Random keys are drawn once per song so comparisons stay consistent
+    let shuffle: Vec<u128> = songs.iter().map(|_| Uuid::new_v4().as_u128()).collect();
+
+    let mut order: Vec<usize> = (0..songs.len()).collect();
+    order.sort_by(|&a, &b| match (songs[a], songs[b]) {
+        (Some(song_a), Some(song_b)) => orders
+            .iter()
+            .map(|(song_order, descending)| {
+                let ordering = match song_order {
+                    SongOrder::Field(compare) => compare(song_a, song_b),
+                    SongOrder::Random => shuffle[a].cmp(&shuffle[b]),
+                };
+                if *descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    });
+
+    Ok(order)
+}
+
+/// Rearrange items into the order given as indexes
+fn apply_order<T: Clone>(items: &mut [T], order: &[usize]) {
+    let sorted: Vec<T> = order.iter().map(|&i| items[i].clone()).collect();
+    items.clone_from_slice(&sorted);
+}
+
+/// Sort songs by several keys, e.g. artist, then album, then track
+pub fn sort_songs_by(songs: &mut [Song], keys: &[SortKey]) -> Result<(), String> {
+    let order = sorted_order(&songs.iter().map(Some).collect::<Vec<_>>(), keys)?;
+    apply_order(songs, &order);
 
     Ok(())
 }
 
-/// Update a single song in the cache
-pub fn update_song_in_cache(
-    song: Song,
-    app_handle: &tauri::AppHandle,
+/// Sort playlist entries by their songs. Entries whose song is gone go last.
+pub fn sort_entries(
+    entries: &mut [PlaylistEntry],
+    songs: &[Song],
+    keys: &[SortKey],
 ) -> Result<(), String> {
-    let mut metadata = load_metadata_cache(app_handle)?;
-
-    // Find and update or add the song
-    if let Some(existing) = metadata.songs.iter_mut().find(|s| s.id == song.id) {
-        *existing = song;
-    } else {
-        metadata.songs.push(song);
-    }
+    let songs_by_id: HashMap<&str, &Song> = songs.iter().map(|s| (s.id.as_str(), s)).collect();
+    let entry_songs: Vec<Option<&Song>> = entries
+        .iter()
+        .map(|e| songs_by_id.get(e.song_id.as_str()).copied())
+        .collect();
 
-    save_metadata_cache(&metadata, app_handle)?;
+    let order = sorted_order(&entry_songs, keys)?;
+    apply_order(entries, &order);
 
     Ok(())
 }
 
-/// Search songs by query (matches title, artist, or album)
-pub fn search_songs(query: &str, songs: &[Song]) -> Vec<Song> {
-    let query_lower = query.to_lowercase();
+/// Check whether songs can be sorted by a field name
+pub fn is_sort_field(field: &str) -> bool {
+    song_order(field).is_ok()
+}
 
-    songs
-        .iter()
-        .filter(|song| {
-            song.title.to_lowercase().contains(&query_lower)
-                || song.artist.to_lowercase().contains(&query_lower)
-                || song.album.to_lowercase().contains(&query_lower)
-        })
-        .cloned()
-        .collect()
+/// Check whether two songs tie on a sort field, e.g. a song before and after an edit.
+/// Songs never tie on a random order.
+pub fn sorts_equal(field: &str, a: &Song, b: &Song) -> bool {
+    match song_order(field) {
+        Ok(SongOrder::Field(compare)) => compare(a, b) == Ordering::Equal,
+        _ => false,
+    }
+}
+
+/// Sort songs by a field name (e.g. "title", "bitrate", "file_size")
+pub fn sort_songs(songs: &mut [Song], sort_by: &str, descending: bool) -> Result<(), String> {
+    sort_songs_by(
+        songs,
+        &[SortKey {
+            field: sort_by.to_string(),
+            descending,
+        }],
+    )
 }
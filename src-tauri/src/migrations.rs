@@ -0,0 +1,408 @@
+use crate::database;
+use crate::snapshots;
+use rusqlite::Connection;
+use serde_json::{json, Value};
+use std::path::Path;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+/// A migration upgrades the schema from one version to the next
+type Migration = fn(&Connection) -> Result<(), String>;
+
+/// Ordered schema migrations. The database's `user_version` is the number applied so far,
+/// so new migrations must only ever be appended.
+const MIGRATIONS: &[Migration] = &[
+    create_initial_schema,
+    add_trash_and_settings,
+    add_genre_and_year,
+    add_smart_playlists,
+    add_playlist_folders,
+    add_playlist_entry_ids,
+    add_playlist_details,
+    add_track_numbers,
+    add_album_artist,
+    add_tags_version,
+    drop_play_counts_and_ratings,
+];
+
+/// Version 1: songs, playlists and playlist entries
+fn create_initial_schema(conn: &Connection) -> Result<(), String> {
+    // IF NOT EXISTS so databases created before versioning pick up at version 1
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS songs (
+            id TEXT PRIMARY KEY,
+            title TEXT NOT NULL,
+            artist TEXT NOT NULL,
+            album TEXT NOT NULL,
+            file_path TEXT NOT NULL,
+            duration_secs INTEGER,
+            album_art TEXT,
+            file_hash TEXT,
+            codec TEXT,
+            bitrate_kbps INTEGER,
+            sample_rate INTEGER,
+            channel_mode TEXT,
+            is_vbr INTEGER,
+            encoder TEXT,
+            file_size INTEGER,
+            play_count INTEGER NOT NULL DEFAULT 0,
+            rating INTEGER
+        );
+        CREATE INDEX IF NOT EXISTS songs_file_hash ON songs (file_hash);
+        CREATE INDEX IF NOT EXISTS songs_artist ON songs (artist);
+        CREATE INDEX IF NOT EXISTS songs_album ON songs (album);
+
+        CREATE TABLE IF NOT EXISTS playlists (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            position INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS playlists_position ON playlists (position);
+
+        CREATE TABLE IF NOT EXISTS playlist_entries (
+            playlist_id TEXT NOT NULL REFERENCES playlists (id) ON DELETE CASCADE,
+            position INTEGER NOT NULL,
+            song_id TEXT NOT NULL,
+            PRIMARY KEY (playlist_id, position)
+        );
+        CREATE INDEX IF NOT EXISTS playlist_entries_song ON playlist_entries (song_id);",
+    )
+    .map_err(|e| format!("Failed to create library schema: {}", e))
+}
+
+/// Version 2: trashed songs and app settings
+fn add_trash_and_settings(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        "CREATE TABLE trash (
+            song_id TEXT PRIMARY KEY,
+            song TEXT NOT NULL,
+            playlists TEXT NOT NULL,
+            deleted_at INTEGER NOT NULL
+        );
+
+        CREATE TABLE settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );",
+    )
+    .map_err(|e| format!("Failed to add trash tables: {}", e))
+}
+
+/// Version 3: genre and year tags
+fn add_genre_and_year(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        "ALTER TABLE songs ADD COLUMN genre TEXT;
+        ALTER TABLE songs ADD COLUMN year INTEGER;",
+    )
+    .map_err(|e| format!("Failed to add genre and year: {}", e))
+}
+
+/// Version 4: smart playlist rules and when each song was added
+fn add_smart_playlists(conn: &Connection) -> Result<(), String> {
+    // Existing songs get added_at 0, meaning unknown, so date rules never match them
+    conn.execute_batch(
+        "ALTER TABLE playlists ADD COLUMN smart TEXT;
+        ALTER TABLE songs ADD COLUMN added_at INTEGER NOT NULL DEFAULT 0;",
+    )
+    .map_err(|e| format!("Failed to add smart playlists: {}", e))
+}
+
+/// Version 5: nestable playlist folders
+fn add_playlist_folders(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        "CREATE TABLE playlist_folders (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            parent_id TEXT,
+            created_at INTEGER NOT NULL,
+            position INTEGER NOT NULL
+        );
+        ALTER TABLE playlists ADD COLUMN folder_id TEXT;",
+    )
+    .map_err(|e| format!("Failed to add playlist folders: {}", e))
+}
+
+/// Version 6: IDs for playlist entries, so a song can appear in a playlist more than once
+fn add_playlist_entry_ids(conn: &Connection) -> Result<(), String> {
+    conn.execute("ALTER TABLE playlist_entries ADD COLUMN id TEXT", [])
+        .map_err(|e| format!("Failed to add playlist entry IDs: {}", e))?;
+
+    let rowids = conn
+        .prepare("SELECT rowid FROM playlist_entries")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| row.get::<_, i64>(0))?
+                .collect::<rusqlite::Result<Vec<i64>>>()
+        })
+        .map_err(|e| format!("Failed to add playlist entry IDs: {}", e))?;
+
+    let mut stmt = conn
+        .prepare("UPDATE playlist_entries SET id = ?1 WHERE rowid = ?2")
+        .map_err(|e| format!("Failed to add playlist entry IDs: {}", e))?;
+    for rowid in rowids {
+        stmt.execute(rusqlite::params![Uuid::new_v4().to_string(), rowid])
+            .map_err(|e| format!("Failed to add playlist entry IDs: {}", e))?;
+    }
+
+    conn.execute(
+        "CREATE UNIQUE INDEX playlist_entries_id ON playlist_entries (id)",
+        [],
+    )
+    .map_err(|e| format!("Failed to add playlist entry IDs: {}", e))?;
+
+    Ok(())
+}
+
+/// Version 7: playlist description, cover, last change and pinning
+fn add_playlist_details(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        "ALTER TABLE playlists ADD COLUMN description TEXT;
+        ALTER TABLE playlists ADD COLUMN cover TEXT;
+        ALTER TABLE playlists ADD COLUMN updated_at INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE playlists ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;
+        UPDATE playlists SET updated_at = created_at;",
+    )
+    .map_err(|e| format!("Failed to add playlist details: {}", e))
+}
+
+/// Version 8: track and disc numbers
+fn add_track_numbers(conn: &Connection) -> Result<(), String> {
+    // Existing songs are filled in from their tags by the startup backfill
+    conn.execute_batch(
+        "ALTER TABLE songs ADD COLUMN track_number INTEGER;
+        ALTER TABLE songs ADD COLUMN disc_number INTEGER;",
+    )
+    .map_err(|e| format!("Failed to add track numbers: {}", e))
+}
+
+/// Version 9: album artist, for grouping albums
+fn add_album_artist(conn: &Connection) -> Result<(), String> {
+    conn.execute("ALTER TABLE songs ADD COLUMN album_artist TEXT", [])
+        .map_err(|e| format!("Failed to add album artist: {}", e))?;
+
+    Ok(())
+}
+
+/// Version 10: which tag fields have been read for each song
+fn add_tags_version(conn: &Connection) -> Result<(), String> {
+    // Existing songs start at 0, so the startup backfill reads their tags once
+    conn.execute(
+        "ALTER TABLE songs ADD COLUMN tags_version INTEGER NOT NULL DEFAULT 0",
+        [],
+    )
+    .map_err(|e| format!("Failed to add tags version: {}", e))?;
+
+    Ok(())
+}
+
+/// Version 11: drop play counts and ratings, which nothing ever set
+fn drop_play_counts_and_ratings(conn: &Connection) -> Result<(), String> {
+    let smart_playlists = conn
+        .prepare("SELECT id, smart FROM playlists WHERE smart IS NOT NULL")
+        .and_then(|mut stmt| {
+            stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<(String, String)>>>()
+        })
+        .map_err(|e| format!("Failed to drop play counts and ratings: {}", e))?;
+
+    // Smart playlists must still load once the fields are gone
+    for (id, smart) in smart_playlists {
+        let mut criteria: Value = serde_json::from_str(&smart)
+            .map_err(|e| format!("Failed to drop play counts and ratings: {}", e))?;
+
+        if let Some(group) = criteria.get_mut("rules") {
+            replace_dropped_rules(group);
+        }
+        if let Some(criteria) = criteria.as_object_mut() {
+            let sort_by = criteria.get("sort_by").and_then(Value::as_str);
+            if matches!(sort_by, Some("play_count" | "rating")) {
+                criteria.remove("sort_by");
+            }
+        }
+
+        conn.execute(
+            "UPDATE playlists SET smart = ?1 WHERE id = ?2",
+            rusqlite::params![criteria.to_string(), id],
+        )
+        .map_err(|e| format!("Failed to drop play counts and ratings: {}", e))?;
+    }
+
+    conn.execute_batch(
+        "ALTER TABLE songs DROP COLUMN play_count;
+        ALTER TABLE songs DROP COLUMN rating;",
+    )
+    .map_err(|e| format!("Failed to drop play counts and ratings: {}", e))
+}
+
+/// Replace smart rules on play count or rating with an empty group that matches the same
+/// songs: every song had 0 plays and no rating. Empty AND groups match every song, and
+/// empty OR groups none.
+fn replace_dropped_rules(group: &mut Value) {
+    let rules = match group.get_mut("rules").and_then(Value::as_array_mut) {
+        Some(rules) => rules,
+        None => return,
+    };
+
+    for rule in rules {
+        if rule.get("rules").is_some() {
+            replace_dropped_rules(rule);
+            continue;
+        }
+
+        let number = |key: &str| rule.get(key).and_then(Value::as_f64).unwrap_or_default();
+        let matches = match rule.get("field").and_then(Value::as_str) {
+            Some("play_count") => match rule.get("op").and_then(Value::as_str) {
+                Some("greater_than") => 0.0 > number("value"),
+                Some("less_than") => 0.0 < number("value"),
+                Some("between") => number("min") <= 0.0 && 0.0 <= number("max"),
+                _ => false,
+            },
+            // Songs without a rating never matched a rating rule
+            Some("rating") => false,
+            _ => continue,
+        };
+
+        *rule = json!({ "match_all": matches, "rules": [] });
+    }
+}
+
+/// Get the schema version of a database
+fn schema_version(conn: &Connection) -> Result<usize, String> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(|e| format!("Failed to read library version: {}", e))
+}
+
+/// Check whether the database has any tables yet
+fn has_tables(conn: &Connection) -> Result<bool, String> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table')",
+        [],
+        |row| row.get(0),
+    )
+    .map_err(|e| format!("Failed to inspect library database: {}", e))
+}
+
+/// Write a copy of the database before migrating it
+fn backup_database(conn: &Connection, version: usize, backup_dir: &Path) -> Result<(), String> {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let backup_path = backup_dir.join(format!("library-v{}-{}.db", version, timestamp));
+
+    snapshots::write_database_copy(conn, &backup_path)
+}
+
+/// Apply any migrations the database hasn't had yet, backing it up before each one
+pub fn run_migrations(conn: &mut Connection, backup_dir: &Path) -> Result<(), String> {
+    let current = schema_version(conn)?;
+
+    if current > MIGRATIONS.len() {
+        return Err(format!(
+            "Library was created by a newer version of the app (schema v{}, this build supports v{})",
+            current,
+            MIGRATIONS.len()
+        ));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        // A brand new database has nothing worth backing up
+        if has_tables(conn)? {
+            backup_database(conn, index, backup_dir)?;
+        }
+
+        let tx = database::begin(conn)?;
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", index + 1)
+            .map_err(|e| format!("Failed to update library version: {}", e))?;
+        database::commit(tx)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database;
+    use crate::smart_playlist;
+    use crate::test_fixtures::song_with;
+    use rusqlite::params;
+
+    #[test]
+    fn dropped_fields_leave_smart_playlists_matching_the_same_songs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut conn = Connection::open(dir.path().join("library.db")).unwrap();
+
+        // Set up a version 10 library with rules on the old fields
+        for (index, migration) in MIGRATIONS[..10].iter().enumerate() {
+            migration(&conn).unwrap();
+            conn.pragma_update(None, "user_version", index + 1).unwrap();
+        }
+        let rules = |rules: Value| {
+            let group = json!({ "match_all": true, "rules": rules });
+            json!({ "rules": group, "sort_by": "rating" })
+        };
+        let smart = [
+            (
+                "unplayed",
+                rules(json!([{ "op": "less_than", "field": "play_count", "value": 1 }])),
+            ),
+            (
+                "played",
+                rules(json!([{ "op": "greater_than", "field": "play_count", "value": 0 }])),
+            ),
+            (
+                "rated",
+                rules(json!([{ "op": "between", "field": "rating", "min": 1, "max": 5 }])),
+            ),
+            (
+                "house",
+                rules(json!([{
+                    "match_all": false,
+                    "rules": [
+                        { "op": "is", "field": "genre", "value": "house" },
+                        { "op": "greater_than", "field": "rating", "value": 3 },
+                    ],
+                }])),
+            ),
+        ];
+        for (id, criteria) in &smart {
+            conn.execute(
+                "INSERT INTO playlists (id, name, created_at, position, smart)
+                 VALUES (?1, ?1, 0, 0, ?2)",
+                params![id, criteria.to_string()],
+            )
+            .unwrap();
+        }
+
+        run_migrations(&mut conn, &dir.path().join("backups")).unwrap();
+
+        let songs = [
+            song_with("a", json!({ "genre": "House" })),
+            song_with("b", json!({ "genre": "Jazz" })),
+        ];
+        let matched = |id: &str| -> Vec<String> {
+            let playlist = database::load_playlists(&conn)
+                .unwrap()
+                .into_iter()
+                .find(|p| p.id == id)
+                .unwrap();
+            assert!(playlist.smart.as_ref().unwrap().sort_by.is_none());
+
+            let resolved = smart_playlist::resolve(playlist, &songs).unwrap();
+            resolved.entries.into_iter().map(|e| e.song_id).collect()
+        };
+
+        assert_eq!(matched("unplayed"), ["a", "b"]);
+        assert!(matched("played").is_empty());
+        assert!(matched("rated").is_empty());
+        assert_eq!(matched("house"), ["a"]);
+
+        // Saving a song no longer touches the dropped columns
+        database::save_song(&conn, &songs[0]).unwrap();
+        assert_eq!(database::load_songs(&conn).unwrap().len(), 1);
+    }
+}
@@ -10,28 +10,508 @@ pub struct Song {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub duration_secs: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub genre: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub year: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub album_art: Option<String>, // Base64-encoded image data
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_hash: Option<String>, // SHA-256 of the audio file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bitrate_kbps: Option<u32>, // Average bitrate for VBR files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_vbr: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoder: Option<String>, // Encoder tag, e.g. "LAME3.100"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_size: Option<u64>,
+    #[serde(default)]
+    pub added_at: u64, // When the song was imported, seconds since the epoch; 0 if unknown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_number: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disc_number: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album_artist: Option<String>, // Albums are grouped by this, or by artist if unset
+    #[serde(default)]
+    pub tags_version: u32, // Which tag fields have been read, see metadata::TAGS_VERSION
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "PlaylistData", into = "PlaylistData")]
 pub struct Playlist {
     pub id: String,
     pub name: String,
-    pub song_ids: Vec<String>,
+    pub entries: Vec<PlaylistEntry>, // Filled in from the rules for smart playlists
+    pub created_at: u64,
+    pub smart: Option<SmartCriteria>,
+    pub folder_id: Option<String>, // None for playlists at the top level
+    pub description: Option<String>,
+    pub cover: Option<String>, // Custom cover as a data URL; None uses a mosaic of album art
+    pub updated_at: u64,
+    pub pinned: bool,
+}
+
+/// A song's place in a playlist. A song can be in a playlist more than once,
+/// so entries have their own IDs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlaylistEntry {
+    pub id: String,
+    pub song_id: String,
+}
+
+/// How a playlist is serialized: its entries plus the plain list of song IDs.
+/// Playlists saved before entries existed only have `song_ids`.
+#[derive(Serialize, Deserialize)]
+struct PlaylistData {
+    id: String,
+    name: String,
+    #[serde(default)]
+    song_ids: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    entries: Option<Vec<PlaylistEntry>>,
+    created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    smart: Option<SmartCriteria>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    folder_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cover: Option<String>,
+    #[serde(default)]
+    updated_at: u64,
+    #[serde(default)]
+    pinned: bool,
+}
+
+impl From<PlaylistData> for Playlist {
+    fn from(data: PlaylistData) -> Self {
+        let entries = data.entries.unwrap_or_else(|| {
+            data.song_ids
+                .iter()
+                .map(|song_id| PlaylistEntry::new(song_id))
+                .collect()
+        });
+
+        Playlist {
+            id: data.id,
+            name: data.name,
+            entries,
+            created_at: data.created_at,
+            smart: data.smart,
+            folder_id: data.folder_id,
+            description: data.description,
+            cover: data.cover,
+            // Playlists saved before this was tracked count as unchanged since creation
+            updated_at: data.updated_at.max(data.created_at),
+            pinned: data.pinned,
+        }
+    }
+}
+
+impl From<Playlist> for PlaylistData {
+    fn from(playlist: Playlist) -> Self {
+        PlaylistData {
+            id: playlist.id,
+            name: playlist.name,
+            song_ids: playlist.entries.iter().map(|e| e.song_id.clone()).collect(),
+            entries: Some(playlist.entries),
+            created_at: playlist.created_at,
+            smart: playlist.smart,
+            folder_id: playlist.folder_id,
+            description: playlist.description,
+            cover: playlist.cover,
+            updated_at: playlist.updated_at,
+            pinned: playlist.pinned,
+        }
+    }
+}
+
+impl PlaylistEntry {
+    /// Create an entry for a song with a new ID
+    pub fn new(song_id: &str) -> Self {
+        PlaylistEntry {
+            id: uuid::Uuid::new_v4().to_string(),
+            song_id: song_id.to_string(),
+        }
+    }
+}
+
+impl Playlist {
+    /// Check whether the playlist has any entry for a song
+    pub fn contains_song(&self, song_id: &str) -> bool {
+        self.entries.iter().any(|e| e.song_id == song_id)
+    }
+
+    /// Remove every entry for a song
+    pub fn remove_song(&mut self, song_id: &str) {
+        self.entries.retain(|e| e.song_id != song_id);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaylistFolder {
+    pub id: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>, // None for folders at the top level
     pub created_at: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextField {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Codec,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NumberField {
+    Year,
+    DurationSecs,
+    BitrateKbps,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Condition {
+    Is {
+        field: TextField,
+        value: String,
+    },
+    IsNot {
+        field: TextField,
+        value: String,
+    },
+    Contains {
+        field: TextField,
+        value: String,
+    },
+    NotContains {
+        field: TextField,
+        value: String,
+    },
+    GreaterThan {
+        field: NumberField,
+        value: f64,
+    },
+    LessThan {
+        field: NumberField,
+        value: f64,
+    },
+    /// Matches min and max themselves too
+    Between {
+        field: NumberField,
+        min: f64,
+        max: f64,
+    },
+    AddedInLastDays {
+        days: u32,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Rule {
+    Group(RuleGroup),
+    Condition(Condition),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleGroup {
+    pub match_all: bool, // AND when true, OR when false
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmartCriteria {
+    pub rules: RuleGroup,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort_by: Option<String>, // Any field get_all_songs can sort by
+    #[serde(default)]
+    pub descending: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Waveform {
+    pub resolution: usize,
+    pub min: Vec<f32>,
+    pub max: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub duration_secs: u32,
+    pub hashes: Vec<u32>, // One 32-bit sub-fingerprint per analysis frame
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateMatch {
+    pub song: Song,
+    pub similarity: f32, // 0.0 - 1.0, relative to the suggested song to keep
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DuplicateGroup {
+    pub keep_id: String, // Highest-quality copy in the group
+    pub matches: Vec<DuplicateMatch>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedEntry {
+    pub playlist_id: String,
+    pub position: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry_id: Option<String>, // Missing for songs trashed before entries had IDs
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedSong {
+    pub song: Song, // file_path is where the song lived in all_songs
+    pub deleted_at: u64,
+    pub playlists: Vec<TrashedEntry>, // Where the song was, to put it back on restore
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Settings {
+    pub trash_retention_days: u32, // 0 keeps trashed songs until the trash is emptied
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            trash_retention_days: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AppMetadata {
     pub songs: Vec<Song>,
     pub playlists: Vec<Playlist>,
+    #[serde(default)]
+    pub folders: Vec<PlaylistFolder>,
+    #[serde(skip)]
+    pub trash: Vec<TrashedSong>, // Not part of metadata exports
+    #[serde(skip)]
+    pub settings: Settings,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub format_version: u32,
+    pub created_at: u64,
+    pub song_count: usize,
+    pub playlist_count: usize,
+    pub includes_audio: bool,
+    #[serde(default)]
+    pub missing_song_ids: Vec<String>, // Songs whose audio wasn't on disk to include
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RestoreMode {
+    Replace, // Make the library exactly match the backup
+    Merge,   // Add songs and playlists the library doesn't already have
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestoreSummary {
+    pub songs_added: usize,
+    pub songs_removed: usize,
+    pub playlists_added: usize,
+    pub playlists_removed: usize,
+    pub skipped_song_ids: Vec<String>, // Audio was neither in the backup nor on disk
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DanglingEntry {
+    pub playlist_id: String,
+    pub song_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryReport {
+    pub orphan_files: Vec<String>, // MP3s in all_songs with no song, relative to all_songs
+    pub missing_files: Vec<Song>,  // Songs whose audio file is gone
+    pub dangling_entries: Vec<DanglingEntry>,
+    pub stale_symlinks: Vec<String>,    // Relative to music/playlists
+    pub background_errors: Vec<String>, // Failures in background work since startup
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepairCategory {
+    OrphanFiles,     // Import them as songs
+    MissingFiles,    // Remove the songs from the library and playlists
+    DanglingEntries, // Remove the entries from their playlists
+    StaleSymlinks,   // Delete them
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CountEntry {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>, // Set for albums, which are counted per artist
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryStats {
+    pub total_songs: usize,
+    pub total_duration_secs: u64,
+    pub total_size_bytes: u64,
+    // Each list is sorted by count, highest first
+    pub artists: Vec<CountEntry>,
+    pub albums: Vec<CountEntry>,
+    pub genres: Vec<CountEntry>,
+    pub formats: Vec<CountEntry>,
+    pub years: Vec<CountEntry>,
+    pub biggest_files: Vec<Song>,
+    pub missing_metadata: Vec<Song>, // Unknown Artist or Unknown Album
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncOptions {
+    #[serde(default)]
+    pub remove_stale: bool, // Delete files synced earlier for these playlists that none has now
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncSummary {
+    pub copied: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub playlists_written: usize,
+    pub missing_song_ids: Vec<String>, // Audio file wasn't found in the library
+}
+
+/// A song found by a search, with how well it matched (higher is better)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SongMatch {
+    pub song: Song,
+    pub score: f32,
+}
+
+/// Songs grouped by album artist and album
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Album {
+    pub id: String, // Derived from the artist and title, so it's stable across restarts
+    pub title: String,
+    pub artist: String,        // Album artist, or the track artist if untagged
+    pub year: Option<i32>,     // Earliest year of its songs
+    pub song_ids: Vec<String>, // In disc and track order, ready to queue
+    pub total_duration_secs: u64,
+    pub album_art: Option<String>, // First cover among its songs
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlbumDetail {
+    pub album: Album,
+    pub songs: Vec<Song>, // Same order as album.song_ids
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Artist {
+    pub id: String,
+    pub name: String,
+    pub album_ids: Vec<String>, // Oldest first
+    pub song_ids: Vec<String>,  // Album by album
+    pub total_duration_secs: u64,
+    pub album_art: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtistDetail {
+    pub artist: Artist,
+    pub albums: Vec<Album>,
+    pub songs: Vec<Song>, // Same order as artist.song_ids
+}
+
+/// One key to sort songs by. `field` is a song field such as "title", "track" (disc,
+/// then track number) or "added_at", or "random" to shuffle.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SortKey {
+    pub field: String,
+    #[serde(default)]
+    pub descending: bool,
+}
+
+/// How to split a playlist into several
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "by", rename_all = "snake_case")]
+pub enum SplitMode {
+    Album,
+    Artist,
+    Songs { count: usize },   // Parts of at most this many songs
+    Minutes { minutes: u32 }, // Parts no longer than this, unless a single song is
 }
 
 impl AppMetadata {
-    pub fn new() -> Self {
-        Self {
-            songs: Vec::new(),
-            playlists: Vec::new(),
-        }
+    /// Find a song by ID
+    pub fn find_song(&self, song_id: &str) -> Option<&Song> {
+        self.songs.iter().find(|s| s.id == song_id)
+    }
+
+    /// Get a song by ID, failing if it doesn't exist
+    pub fn get_song(&self, song_id: &str) -> Result<&Song, String> {
+        self.find_song(song_id)
+            .ok_or_else(|| format!("Song not found: {}", song_id))
+    }
+
+    /// Find a playlist by ID
+    pub fn find_playlist(&self, playlist_id: &str) -> Option<&Playlist> {
+        self.playlists.iter().find(|p| p.id == playlist_id)
+    }
+
+    /// Get a playlist by ID, failing if it doesn't exist
+    pub fn get_playlist(&self, playlist_id: &str) -> Result<&Playlist, String> {
+        self.find_playlist(playlist_id)
+            .ok_or_else(|| format!("Playlist not found: {}", playlist_id))
+    }
+
+    /// Find a playlist folder by ID
+    pub fn find_folder(&self, folder_id: &str) -> Option<&PlaylistFolder> {
+        self.folders.iter().find(|f| f.id == folder_id)
+    }
+
+    /// Get a playlist folder by ID, failing if it doesn't exist
+    pub fn get_folder(&self, folder_id: &str) -> Result<&PlaylistFolder, String> {
+        self.find_folder(folder_id)
+            .ok_or_else(|| format!("Folder not found: {}", folder_id))
+    }
+
+    /// Get the IDs of playlists that contain a song
+    pub fn playlists_containing(&self, song_id: &str) -> Vec<String> {
+        self.playlists
+            .iter()
+            .filter(|p| p.contains_song(song_id))
+            .map(|p| p.id.clone())
+            .collect()
+    }
+
+    /// Check whether any song other than `except_id` has the given file hash
+    pub fn file_hash_in_use(&self, file_hash: &str, except_id: &str) -> bool {
+        self.songs
+            .iter()
+            .any(|s| s.id != except_id && s.file_hash.as_deref() == Some(file_hash))
+    }
+
+    /// Check whether any song's file is at the given path, relative to all_songs/
+    pub fn file_path_in_use(&self, file_path: &str) -> bool {
+        self.songs.iter().any(|s| s.file_path == file_path)
     }
 }
@@ -0,0 +1,146 @@
+use crate::models::{Playlist, Song};
+use base64::{engine::general_purpose, Engine as _};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageReader, RgbImage};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Custom covers are scaled down to fit within this many pixels
+const MAX_COVER_SIZE: u32 = 600;
+
+/// Size of each album's tile in a generated mosaic
+const TILE_SIZE: u32 = 300;
+
+const JPEG_QUALITY: u8 = 85;
+
+/// How many generated mosaics to keep
+const KEEP_MOSAICS: usize = 32;
+
+/// Recently generated mosaics and the album art each was made from, least recently used first
+static MOSAICS: Mutex<Vec<(Vec<String>, String)>> = Mutex::new(Vec::new());
+
+/// Decode the image in a base64 data URL, as album art is stored
+fn decode_data_url(data_url: &str) -> Option<DynamicImage> {
+    let (_, data) = data_url.split_once(";base64,")?;
+    let bytes = general_purpose::STANDARD.decode(data).ok()?;
+
+    ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()?
+        .decode()
+        .ok()
+}
+
+/// Encode an image as a JPEG data URL
+fn encode_data_url(image: &RgbImage) -> Result<String, String> {
+    let mut bytes = Vec::new();
+    JpegEncoder::new_with_quality(&mut bytes, JPEG_QUALITY)
+        .encode_image(image)
+        .map_err(|e| format!("Failed to encode cover: {}", e))?;
+
+    Ok(format!(
+        "data:image/jpeg;base64,{}",
+        general_purpose::STANDARD.encode(bytes)
+    ))
+}
+
+/// Load an image file for use as a playlist cover
+pub fn load_cover(image_path: &Path) -> Result<String, String> {
+    let image = ImageReader::open(image_path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| format!("Failed to open cover image: {}", e))?
+        .decode()
+        .map_err(|e| format!("Failed to read cover image: {}", e))?;
+
+    // Keep the library small; covers are never shown larger than this
+    let image = if image.width() > MAX_COVER_SIZE || image.height() > MAX_COVER_SIZE {
+        image.resize(MAX_COVER_SIZE, MAX_COVER_SIZE, FilterType::Triangle)
+    } else {
+        image
+    };
+
+    encode_data_url(&image.to_rgb8())
+}
+
+/// Tile four album covers into a 2x2 mosaic
+fn mosaic(covers: &[DynamicImage]) -> Result<String, String> {
+    let mut canvas = RgbImage::new(TILE_SIZE * 2, TILE_SIZE * 2);
+
+    for (index, cover) in covers.iter().take(4).enumerate() {
+        let tile = cover
+            .resize_to_fill(TILE_SIZE, TILE_SIZE, FilterType::Triangle)
+            .to_rgb8();
+        let x = (index as u32 % 2) * TILE_SIZE;
+        let y = (index as u32 / 2) * TILE_SIZE;
+        imageops::replace(&mut canvas, &tile, x.into(), y.into());
+    }
+
+    encode_data_url(&canvas)
+}
+
+/// The art a playlist's cover is made from: its custom cover alone, or else the first four
+/// different album covers in it
+pub fn cover_art(playlist: &Playlist, songs: &[Song]) -> Vec<String> {
+    if let Some(cover) = &playlist.cover {
+        return vec![cover.clone()];
+    }
+
+    let songs_by_id: HashMap<&str, &Song> = songs.iter().map(|s| (s.id.as_str(), s)).collect();
+
+    let mut album_art: Vec<&str> = Vec::new();
+    for entry in &playlist.entries {
+        let art = songs_by_id
+            .get(entry.song_id.as_str())
+            .and_then(|song| song.album_art.as_deref());
+
+        if let Some(art) = art {
+            if !album_art.contains(&art) {
+                album_art.push(art);
+                if album_art.len() == 4 {
+                    break;
+                }
+            }
+        }
+    }
+
+    album_art.into_iter().map(String::from).collect()
+}
+
+/// Cover to show for art from `cover_art`: a mosaic of four album covers, or else the first
+/// one as it is. Mosaics are cached, so a playlist only builds one when its art changes.
+pub fn playlist_cover(art: &[String]) -> Result<Option<String>, String> {
+    if art.len() < 4 {
+        return Ok(art.first().cloned());
+    }
+
+    let mut mosaics = MOSAICS
+        .lock()
+        .map_err(|_| "Cover cache is unavailable".to_string())?;
+    if let Some(index) = mosaics.iter().position(|(key, _)| key.as_slice() == art) {
+        let cached = mosaics.remove(index);
+        let cover = cached.1.clone();
+        mosaics.push(cached);
+        return Ok(Some(cover));
+    }
+    drop(mosaics);
+
+    let covers: Option<Vec<DynamicImage>> = art.iter().map(|a| decode_data_url(a)).collect();
+    let Some(covers) = covers else {
+        // Art that can't be decoded doesn't make a mosaic
+        return Ok(art.first().cloned());
+    };
+    let cover = mosaic(&covers)?;
+
+    let mut mosaics = MOSAICS
+        .lock()
+        .map_err(|_| "Cover cache is unavailable".to_string())?;
+    if mosaics.len() >= KEEP_MOSAICS {
+        mosaics.remove(0);
+    }
+    mosaics.push((art.to_vec(), cover.clone()));
+
+    Ok(Some(cover))
+}
@@ -1,8 +1,41 @@
-use crate::filesystem;
+use crate::browse;
+use crate::library::{Library, Writer};
 use crate::metadata;
-use crate::models::Playlist;
-use std::fs;
+use crate::models::{AppMetadata, Playlist, PlaylistEntry, SmartCriteria, SortKey, SplitMode};
+use crate::playlist_cover;
+use crate::search;
+use crate::smart_playlist;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::path::Path;
 use std::time::SystemTime;
+use tauri::Manager;
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// A new, empty playlist at the top level
+fn new_playlist(name: String) -> Playlist {
+    use uuid::Uuid;
+
+    let now = now_secs();
+    Playlist {
+        id: Uuid::new_v4().to_string(),
+        name,
+        entries: Vec::new(),
+        created_at: now,
+        smart: None,
+        folder_id: None,
+        description: None,
+        cover: None,
+        updated_at: now,
+        pinned: false,
+    }
+}
 
 /// Create a new playlist with the given songs
 pub fn create_playlist(
@@ -10,148 +43,157 @@ pub fn create_playlist(
     song_ids: Vec<String>,
     app_handle: &tauri::AppHandle,
 ) -> Result<Playlist, String> {
-    use uuid::Uuid;
+    app_handle.state::<Library>().write(|library| {
+        // Create playlist metadata
+        let mut playlist = new_playlist(name);
+        playlist.entries = song_ids.iter().map(|id| PlaylistEntry::new(id)).collect();
 
-    let app_data_path = filesystem::get_app_data_path(app_handle)?;
-    let playlists_dir = app_data_path.join("music/playlists");
-    let all_songs_dir = app_data_path.join("music/all_songs");
+        // Save to the library
+        library.save_playlist(&playlist)?;
 
-    // Generate unique ID for playlist
-    let playlist_id = Uuid::new_v4().to_string();
-    let playlist_dir = playlists_dir.join(&playlist_id);
+        Ok(playlist)
+    })
+}
 
-    // Create playlist directory
-    fs::create_dir_all(&playlist_dir)
-        .map_err(|e| format!("Failed to create playlist directory: {}", e))?;
+/// Create a smart playlist whose songs are chosen by rules
+pub fn create_smart_playlist(
+    name: String,
+    criteria: SmartCriteria,
+    app_handle: &tauri::AppHandle,
+) -> Result<Playlist, String> {
+    smart_playlist::validate(&criteria)?;
+
+    app_handle.state::<Library>().write(|library| {
+        // Entries are filled in from the rules whenever the playlist is read
+        let mut playlist = new_playlist(name);
+        playlist.smart = Some(criteria);
+
+        library.save_playlist(&playlist)?;
+
+        smart_playlist::resolve(playlist, &library.songs)
+    })
+}
 
-    // Load metadata to get song file paths
-    let metadata_cache = metadata::load_metadata_cache(app_handle)?;
+/// Replace the rules of a smart playlist
+pub fn update_smart_playlist(
+    playlist_id: String,
+    criteria: SmartCriteria,
+    app_handle: &tauri::AppHandle,
+) -> Result<Playlist, String> {
+    smart_playlist::validate(&criteria)?;
 
-    // Create symlinks for each song
-    for song_id in &song_ids {
-        if let Some(song) = metadata_cache.songs.iter().find(|s| s.id == *song_id) {
-            let song_path = all_songs_dir.join(&song.file_path);
-            filesystem::create_playlist_symlink(&song_path, &playlist_id, app_handle)?;
+    app_handle.state::<Library>().write(|library| {
+        let mut playlist = library.get_playlist(&playlist_id)?.clone();
+        if playlist.smart.is_none() {
+            return Err(format!("Not a smart playlist: {}", playlist_id));
         }
+
+        playlist.smart = Some(criteria);
+        playlist.updated_at = now_secs();
+        library.save_playlist(&playlist)?;
+
+        smart_playlist::resolve(playlist, &library.songs)
+    })
+}
+
+/// Get a playlist for editing its songs, rejecting smart playlists
+fn get_static_playlist(library: &AppMetadata, playlist_id: &str) -> Result<Playlist, String> {
+    let playlist = library.get_playlist(playlist_id)?;
+    if playlist.smart.is_some() {
+        return Err("Smart playlists are updated automatically".to_string());
     }
 
-    // Create playlist metadata
-    let playlist = Playlist {
-        id: playlist_id,
-        name,
-        song_ids,
-        created_at: SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
-    };
-
-    // Save to metadata cache
-    let mut metadata_cache = metadata::load_metadata_cache(app_handle)?;
-    metadata_cache.playlists.push(playlist.clone());
-    metadata::save_metadata_cache(&metadata_cache, app_handle)?;
-
-    Ok(playlist)
+    Ok(playlist.clone())
 }
 
 /// Get all playlists
 pub fn get_all_playlists(app_handle: &tauri::AppHandle) -> Result<Vec<Playlist>, String> {
-    let metadata_cache = metadata::load_metadata_cache(app_handle)?;
-    Ok(metadata_cache.playlists)
+    app_handle.state::<Library>().read(|library| {
+        library
+            .playlists
+            .iter()
+            .map(|playlist| smart_playlist::resolve(playlist.clone(), &library.songs))
+            .collect()
+    })
 }
 
 /// Get a specific playlist by ID
 pub fn get_playlist(id: String, app_handle: &tauri::AppHandle) -> Result<Playlist, String> {
-    let metadata_cache = metadata::load_metadata_cache(app_handle)?;
-
-    metadata_cache
-        .playlists
-        .into_iter()
-        .find(|p| p.id == id)
-        .ok_or_else(|| format!("Playlist not found: {}", id))
+    app_handle
+        .state::<Library>()
+        .read(|library| smart_playlist::resolve(library.get_playlist(&id)?.clone(), &library.songs))
 }
 
-/// Add songs to an existing playlist
+/// Add songs to the end of a playlist. A song already in the playlist is added again.
+/// Returns the new entries.
 pub fn add_songs_to_playlist(
     playlist_id: String,
     song_ids: Vec<String>,
     app_handle: &tauri::AppHandle,
-) -> Result<(), String> {
-    let app_data_path = filesystem::get_app_data_path(app_handle)?;
-    let all_songs_dir = app_data_path.join("music/all_songs");
-
-    // Load metadata
-    let mut metadata_cache = metadata::load_metadata_cache(app_handle)?;
-
-    // Find the playlist
-    let playlist = metadata_cache
-        .playlists
-        .iter_mut()
-        .find(|p| p.id == playlist_id)
-        .ok_or_else(|| format!("Playlist not found: {}", playlist_id))?;
-
-    // Add songs to playlist
-    for song_id in &song_ids {
-        if !playlist.song_ids.contains(song_id) {
-            playlist.song_ids.push(song_id.clone());
-
-            // Create symlink
-            if let Some(song) = metadata_cache.songs.iter().find(|s| s.id == *song_id) {
-                let song_path = all_songs_dir.join(&song.file_path);
-                filesystem::create_playlist_symlink(&song_path, &playlist_id, app_handle)?;
-            }
+) -> Result<Vec<PlaylistEntry>, String> {
+    app_handle.state::<Library>().write(|library| {
+        // Find the playlist
+        let mut playlist = get_static_playlist(library, &playlist_id)?;
+
+        // Make sure every song is in the library
+        for song_id in &song_ids {
+            library.get_song(song_id)?;
         }
-    }
 
-    // Save metadata
-    metadata::save_metadata_cache(&metadata_cache, app_handle)?;
+        // Add songs to playlist
+        let added: Vec<PlaylistEntry> = song_ids.iter().map(|id| PlaylistEntry::new(id)).collect();
+        playlist.entries.extend(added.iter().cloned());
+        playlist.updated_at = now_secs();
 
-    Ok(())
+        // Save playlist
+        library.save_playlist(&playlist)?;
+
+        Ok(added)
+    })
 }
 
-/// Remove songs from a playlist
+/// Remove every entry for the given songs from a playlist
 pub fn remove_songs_from_playlist(
     playlist_id: String,
     song_ids: Vec<String>,
     app_handle: &tauri::AppHandle,
 ) -> Result<(), String> {
-    let app_data_path = filesystem::get_app_data_path(app_handle)?;
-    let playlist_dir = app_data_path
-        .join("music/playlists")
-        .join(&playlist_id);
-
-    // Load metadata
-    let mut metadata_cache = metadata::load_metadata_cache(app_handle)?;
-
-    // Find the playlist
-    let playlist = metadata_cache
-        .playlists
-        .iter_mut()
-        .find(|p| p.id == playlist_id)
-        .ok_or_else(|| format!("Playlist not found: {}", playlist_id))?;
-
-    // Remove songs from playlist
-    for song_id in &song_ids {
-        playlist.song_ids.retain(|id| id != song_id);
-
-        // Remove symlink
-        if let Some(song) = metadata_cache.songs.iter().find(|s| s.id == *song_id) {
-            let song_filename = std::path::Path::new(&song.file_path)
-                .file_name()
-                .ok_or_else(|| "Invalid song path".to_string())?;
-            let link_path = playlist_dir.join(song_filename);
-
-            if link_path.exists() {
-                fs::remove_file(&link_path)
-                    .map_err(|e| format!("Failed to remove symlink: {}", e))?;
-            }
+    app_handle.state::<Library>().write(|library| {
+        // Find the playlist
+        let mut playlist = get_static_playlist(library, &playlist_id)?;
+
+        // Remove songs from playlist
+        for song_id in &song_ids {
+            playlist.remove_song(song_id);
         }
-    }
+        playlist.updated_at = now_secs();
 
-    // Save metadata
-    metadata::save_metadata_cache(&metadata_cache, app_handle)?;
+        // Save playlist
+        library.save_playlist(&playlist)
+    })
+}
 
-    Ok(())
+/// Remove individual entries from a playlist
+pub fn remove_playlist_entries(
+    playlist_id: String,
+    entry_ids: Vec<String>,
+    app_handle: &tauri::AppHandle,
+) -> Result<(), String> {
+    app_handle.state::<Library>().write(|library| {
+        let mut playlist = get_static_playlist(library, &playlist_id)?;
+
+        for entry_id in &entry_ids {
+            let index = playlist
+                .entries
+                .iter()
+                .position(|e| e.id == *entry_id)
+                .ok_or_else(|| format!("Playlist entry not found: {}", entry_id))?;
+            playlist.entries.remove(index);
+        }
+        playlist.updated_at = now_secs();
+
+        library.save_playlist(&playlist)
+    })
 }
 
 /// Rename a playlist
@@ -161,139 +203,505 @@ pub fn rename_playlist(
     app_handle: &tauri::AppHandle,
 ) -> Result<Playlist, String> {
     // Update metadata - just change the name, keep the ID the same
-    let mut metadata_cache = metadata::load_metadata_cache(app_handle)?;
-    let playlist = metadata_cache
-        .playlists
-        .iter_mut()
-        .find(|p| p.id == playlist_id)
-        .ok_or_else(|| format!("Playlist not found: {}", playlist_id))?;
+    app_handle.state::<Library>().write(|library| {
+        let mut playlist = library.get_playlist(&playlist_id)?.clone();
+        playlist.name = new_name;
+        playlist.updated_at = now_secs();
 
-    playlist.name = new_name;
+        // Save playlist
+        library.save_playlist(&playlist)?;
 
-    let updated_playlist = playlist.clone();
+        Ok(playlist)
+    })
+}
 
-    // Save metadata
-    metadata::save_metadata_cache(&metadata_cache, app_handle)?;
+/// Set or clear a playlist's description
+pub fn set_playlist_description(
+    playlist_id: String,
+    description: Option<String>,
+    app_handle: &tauri::AppHandle,
+) -> Result<Playlist, String> {
+    // A blank description is the same as none
+    let description = description
+        .map(|d| d.trim().to_string())
+        .filter(|d| !d.is_empty());
 
-    Ok(updated_playlist)
+    app_handle.state::<Library>().write(|library| {
+        let mut playlist = library.get_playlist(&playlist_id)?.clone();
+        playlist.description = description;
+        playlist.updated_at = now_secs();
+
+        library.save_playlist(&playlist)?;
+
+        Ok(playlist)
+    })
 }
 
-/// Delete a playlist
-pub fn delete_playlist(id: String, app_handle: &tauri::AppHandle) -> Result<(), String> {
-    let app_data_path = filesystem::get_app_data_path(app_handle)?;
-    let playlist_dir = app_data_path.join("music/playlists").join(&id);
+/// Use an image file as a playlist's cover, or go back to the album art mosaic with None
+pub fn set_playlist_cover(
+    playlist_id: String,
+    image_path: Option<String>,
+    app_handle: &tauri::AppHandle,
+) -> Result<Playlist, String> {
+    // Decode the image before taking the library lock
+    let cover = image_path
+        .map(|path| playlist_cover::load_cover(Path::new(&path)))
+        .transpose()?;
+
+    app_handle.state::<Library>().write(|library| {
+        let mut playlist = library.get_playlist(&playlist_id)?.clone();
+        playlist.cover = cover;
+        playlist.updated_at = now_secs();
 
-    // Delete directory
-    if playlist_dir.exists() {
-        fs::remove_dir_all(&playlist_dir)
-            .map_err(|e| format!("Failed to delete playlist directory: {}", e))?;
+        library.save_playlist(&playlist)?;
+
+        Ok(playlist)
+    })
+}
+
+/// Get the cover to show for a playlist, as a data URL
+pub fn get_playlist_cover(
+    playlist_id: String,
+    app_handle: &tauri::AppHandle,
+) -> Result<Option<String>, String> {
+    let art = app_handle.state::<Library>().read(|library| {
+        let playlist =
+            smart_playlist::resolve(library.get_playlist(&playlist_id)?.clone(), &library.songs)?;
+        Ok(playlist_cover::cover_art(&playlist, &library.songs))
+    })?;
+
+    playlist_cover::playlist_cover(&art)
+}
+
+/// Pin or unpin a playlist
+pub fn set_playlist_pinned(
+    playlist_id: String,
+    pinned: bool,
+    app_handle: &tauri::AppHandle,
+) -> Result<Playlist, String> {
+    app_handle.state::<Library>().write(|library| {
+        let mut playlist = library.get_playlist(&playlist_id)?.clone();
+        playlist.pinned = pinned;
+        playlist.updated_at = now_secs();
+
+        library.save_playlist(&playlist)?;
+
+        Ok(playlist)
+    })
+}
+
+/// Save new playlists and place them straight after another playlist, in the same folder
+fn save_after(
+    library: &mut Writer,
+    after_id: &str,
+    playlists: &mut [Playlist],
+) -> Result<(), String> {
+    let folder_id = library.get_playlist(after_id)?.folder_id.clone();
+    for playlist in playlists.iter_mut() {
+        playlist.folder_id = folder_id.clone();
+        library.save_playlist(playlist)?;
     }
 
-    // Remove from metadata
-    let mut metadata_cache = metadata::load_metadata_cache(app_handle)?;
-    metadata_cache.playlists.retain(|p| p.id != id);
+    let mut order: Vec<String> = library.playlists.iter().map(|p| p.id.clone()).collect();
+    order.retain(|id| !playlists.iter().any(|p| p.id == *id));
+    let at = order
+        .iter()
+        .position(|id| id == after_id)
+        .map_or(0, |i| i + 1);
+    order.splice(at..at, playlists.iter().map(|p| p.id.clone()));
 
-    // Save metadata
-    metadata::save_metadata_cache(&metadata_cache, app_handle)?;
+    library.set_playlist_order(&order)
+}
 
-    Ok(())
+/// Copy a playlist, placing the copy after the original. Smart playlists stay smart.
+pub fn duplicate_playlist(
+    playlist_id: String,
+    app_handle: &tauri::AppHandle,
+) -> Result<Playlist, String> {
+    app_handle.state::<Library>().write(|library| {
+        let original = library.get_playlist(&playlist_id)?.clone();
+
+        let mut copy = new_playlist(format!("{} (copy)", original.name));
+        copy.entries = original
+            .entries
+            .iter()
+            .map(|e| PlaylistEntry::new(&e.song_id))
+            .collect();
+        copy.smart = original.smart;
+        copy.description = original.description;
+        copy.cover = original.cover;
+
+        let mut saved = vec![copy];
+        save_after(library, &playlist_id, &mut saved)?;
+
+        smart_playlist::resolve(saved.remove(0), &library.songs)
+    })
+}
+
+/// Combine playlists, in the order given, into a new playlist. The originals are kept.
+/// With `dedupe`, only the first occurrence of each song is kept.
+pub fn merge_playlists(
+    playlist_ids: Vec<String>,
+    name: String,
+    dedupe: bool,
+    app_handle: &tauri::AppHandle,
+) -> Result<Playlist, String> {
+    if playlist_ids.is_empty() {
+        return Err("No playlists to merge".to_string());
+    }
+
+    app_handle.state::<Library>().write(|library| {
+        let mut merged = new_playlist(name);
+        let mut seen = HashSet::new();
+
+        for playlist_id in &playlist_ids {
+            // Smart playlists contribute the songs they have right now
+            let playlist = smart_playlist::resolve(
+                library.get_playlist(playlist_id)?.clone(),
+                &library.songs,
+            )?;
+
+            for entry in &playlist.entries {
+                if !dedupe || seen.insert(entry.song_id.clone()) {
+                    merged.entries.push(PlaylistEntry::new(&entry.song_id));
+                }
+            }
+        }
+
+        let mut saved = vec![merged];
+        save_after(library, &playlist_ids[0], &mut saved)?;
+
+        Ok(saved.remove(0))
+    })
+}
+
+/// Split a playlist into new playlists, placed after the original. The original is kept.
+pub fn split_playlist(
+    playlist_id: String,
+    mode: SplitMode,
+    app_handle: &tauri::AppHandle,
+) -> Result<Vec<Playlist>, String> {
+    match mode {
+        SplitMode::Songs { count: 0 } => {
+            return Err("Parts must hold at least one song".to_string())
+        }
+        SplitMode::Minutes { minutes: 0 } => {
+            return Err("Parts must be at least one minute long".to_string())
+        }
+        _ => {}
+    }
+
+    app_handle.state::<Library>().write(|library| {
+        let playlist =
+            smart_playlist::resolve(library.get_playlist(&playlist_id)?.clone(), &library.songs)?;
+
+        // Each part is a name and its songs, in the order the songs first appear
+        let mut parts: Vec<(String, Vec<String>)> = Vec::new();
+        let songs = playlist
+            .entries
+            .iter()
+            .filter_map(|e| library.find_song(&e.song_id));
+
+        match mode {
+            SplitMode::Album | SplitMode::Artist => {
+                // Albums are told apart by who they're filed under, as in the album view
+                let mut groups: Vec<((&str, &str), Vec<String>)> = Vec::new();
+                for song in songs {
+                    let key = match mode {
+                        SplitMode::Album => (browse::album_artist(song), song.album.as_str()),
+                        _ => ("", song.artist.as_str()),
+                    };
+                    match groups.iter_mut().find(|(k, _)| *k == key) {
+                        Some((_, song_ids)) => song_ids.push(song.id.clone()),
+                        None => groups.push((key, vec![song.id.clone()])),
+                    }
+                }
+
+                for ((artist, name), song_ids) in &groups {
+                    // Albums sharing a title are named with their artist too
+                    let shared = groups.iter().filter(|((_, n), _)| n == name).count() > 1;
+                    let name = if shared {
+                        format!("{} - {} ({})", playlist.name, name, artist)
+                    } else {
+                        format!("{} - {}", playlist.name, name)
+                    };
+                    parts.push((name, song_ids.clone()));
+                }
+            }
+            SplitMode::Songs { count } => {
+                let song_ids: Vec<String> = songs.map(|s| s.id.clone()).collect();
+                for chunk in song_ids.chunks(count) {
+                    parts.push((String::new(), chunk.to_vec()));
+                }
+            }
+            SplitMode::Minutes { minutes } => {
+                let limit = minutes as u64 * 60;
+                let mut length = 0;
+
+                // Songs of unknown length don't count towards a part's length
+                for song in songs {
+                    let duration = song.duration_secs.unwrap_or(0) as u64;
+                    match parts.last_mut() {
+                        Some((_, song_ids)) if length + duration <= limit => {
+                            song_ids.push(song.id.clone());
+                            length += duration;
+                        }
+                        _ => {
+                            parts.push((String::new(), vec![song.id.clone()]));
+                            length = duration;
+                        }
+                    }
+                }
+            }
+        }
+
+        if let SplitMode::Songs { .. } | SplitMode::Minutes { .. } = mode {
+            for (index, (name, _)) in parts.iter_mut().enumerate() {
+                *name = format!("{} - Part {}", playlist.name, index + 1);
+            }
+        }
+
+        let mut new_playlists: Vec<Playlist> = parts
+            .into_iter()
+            .map(|(name, song_ids)| {
+                let mut part = new_playlist(name);
+                part.entries = song_ids.iter().map(|id| PlaylistEntry::new(id)).collect();
+                part
+            })
+            .collect();
+
+        save_after(library, &playlist_id, &mut new_playlists)?;
+
+        Ok(new_playlists)
+    })
 }
 
-/// Search playlists by name
+/// Delete a playlist
+pub fn delete_playlist(id: String, app_handle: &tauri::AppHandle) -> Result<(), String> {
+    // Remove from the library, which also removes its folder
+    app_handle
+        .state::<Library>()
+        .write(|library| library.delete_playlist(&id))
+}
+
+/// Search playlists by name, ignoring case and accents
 pub fn search_playlists(query: &str, playlists: &[Playlist]) -> Vec<Playlist> {
-    let query_lower = query.to_lowercase();
+    let query = search::fold(query);
 
     playlists
         .iter()
-        .filter(|playlist| playlist.name.to_lowercase().contains(&query_lower))
+        .filter(|playlist| search::fold(&playlist.name).contains(&query))
         .cloned()
         .collect()
 }
 
-/// Reorder songs in a playlist
+/// Reorder songs in a playlist. The new order must list each song as many times as the
+/// playlist has it, so a stale list can't drop or add songs. Repeats keep their relative order.
 pub fn reorder_playlist_songs(
     playlist_id: String,
     song_ids: Vec<String>,
     app_handle: &tauri::AppHandle,
 ) -> Result<(), String> {
-    // Update metadata with new song order
-    let mut metadata_cache = metadata::load_metadata_cache(app_handle)?;
+    // Update playlist with new song order
+    app_handle.state::<Library>().write(|library| {
+        let mut playlist = get_static_playlist(library, &playlist_id)?;
+
+        // Match each song to its next unused entry
+        let mut remaining = playlist.entries;
+        let mut entries = Vec::with_capacity(song_ids.len());
+        for song_id in &song_ids {
+            let index = remaining
+                .iter()
+                .position(|e| e.song_id == *song_id)
+                .ok_or_else(|| format!("Song not in playlist: {}", song_id))?;
+            entries.push(remaining.remove(index));
+        }
 
-    let playlist = metadata_cache
-        .playlists
-        .iter_mut()
-        .find(|p| p.id == playlist_id)
-        .ok_or_else(|| format!("Playlist not found: {}", playlist_id))?;
+        if !remaining.is_empty() {
+            return Err("New order must include every song in the playlist".to_string());
+        }
+        playlist.entries = entries;
+        playlist.updated_at = now_secs();
 
-    playlist.song_ids = song_ids;
+        library.save_playlist(&playlist)
+    })
+}
 
-    metadata::save_metadata_cache(&metadata_cache, app_handle)?;
+/// Reorder the entries of a playlist. Every entry must be listed exactly once.
+pub fn reorder_playlist_entries(
+    playlist_id: String,
+    entry_ids: Vec<String>,
+    app_handle: &tauri::AppHandle,
+) -> Result<(), String> {
+    app_handle.state::<Library>().write(|library| {
+        let mut playlist = get_static_playlist(library, &playlist_id)?;
+
+        let mut remaining = playlist.entries;
+        let mut entries = Vec::with_capacity(entry_ids.len());
+        for entry_id in &entry_ids {
+            let index = remaining
+                .iter()
+                .position(|e| e.id == *entry_id)
+                .ok_or_else(|| format!("Playlist entry not found: {}", entry_id))?;
+            entries.push(remaining.remove(index));
+        }
 
-    Ok(())
+        if !remaining.is_empty() {
+            return Err("New order must include every entry in the playlist".to_string());
+        }
+        playlist.entries = entries;
+        playlist.updated_at = now_secs();
+
+        library.save_playlist(&playlist)
+    })
 }
 
-/// Reorder playlists to match a new order
-pub fn reorder_playlists(
-    playlist_ids: Vec<String>,
+/// Insert songs into a playlist before the entry at `index`, or at the end if `index` is
+/// the playlist's length. Returns the new entries.
+pub fn insert_songs_at(
+    playlist_id: String,
+    index: usize,
+    song_ids: Vec<String>,
     app_handle: &tauri::AppHandle,
+) -> Result<Vec<PlaylistEntry>, String> {
+    app_handle.state::<Library>().write(|library| {
+        let mut playlist = get_static_playlist(library, &playlist_id)?;
+        if index > playlist.entries.len() {
+            return Err(format!(
+                "Position {} is past the end of the playlist ({} songs)",
+                index,
+                playlist.entries.len()
+            ));
+        }
+
+        for song_id in &song_ids {
+            library.get_song(song_id)?;
+        }
+
+        let added: Vec<PlaylistEntry> = song_ids.iter().map(|id| PlaylistEntry::new(id)).collect();
+        playlist.entries.splice(index..index, added.iter().cloned());
+        playlist.updated_at = now_secs();
+
+        library.save_playlist(&playlist)?;
+
+        Ok(added)
+    })
+}
+
+/// Move a range of entries so it starts at `to_index`, counted in the list without the
+/// moved entries
+fn move_range(
+    entries: &mut Vec<PlaylistEntry>,
+    from_range: Range<usize>,
+    to_index: usize,
 ) -> Result<(), String> {
-    let mut metadata_cache = metadata::load_metadata_cache(app_handle)?;
+    let len = entries.len();
 
-    // Create a new ordered playlist array
-    let mut new_playlists = Vec::new();
+    if from_range.start >= from_range.end || from_range.end > len {
+        return Err(format!(
+            "Invalid range {}..{} for a playlist of {} songs",
+            from_range.start, from_range.end, len
+        ));
+    }
 
-    for id in playlist_ids {
-        if let Some(playlist) = metadata_cache.playlists.iter().find(|p| p.id == id) {
-            new_playlists.push(playlist.clone());
-        }
+    let remaining = len - from_range.len();
+    if to_index > remaining {
+        return Err(format!(
+            "Position {} is past the end of the playlist ({} songs without those moved)",
+            to_index, remaining
+        ));
     }
 
-    // Update the metadata cache with the new order
-    metadata_cache.playlists = new_playlists;
-    metadata::save_metadata_cache(&metadata_cache, app_handle)?;
+    let moved: Vec<PlaylistEntry> = entries.drain(from_range).collect();
+    entries.splice(to_index..to_index, moved);
 
     Ok(())
 }
 
-/// Remove duplicate playlists (keeping the first occurrence of each unique ID)
-pub fn deduplicate_playlists(app_handle: &tauri::AppHandle) -> Result<(), String> {
-    let mut metadata_cache = metadata::load_metadata_cache(app_handle)?;
-    let original_count = metadata_cache.playlists.len();
-
-    // Track seen IDs and keep only first occurrence
-    let mut seen_ids = std::collections::HashSet::new();
-    let mut unique_playlists = Vec::new();
-
-    for playlist in metadata_cache.playlists {
-        if seen_ids.insert(playlist.id.clone()) {
-            unique_playlists.push(playlist);
-        } else {
-            // Delete the duplicate playlist directory if it exists
-            let app_data_path = filesystem::get_app_data_path(app_handle)?;
-            let playlist_dir = app_data_path.join("music/playlists").join(&playlist.id);
-
-            if playlist_dir.exists() {
-                let _ = fs::remove_dir_all(&playlist_dir);
-            }
-        }
-    }
+/// Move a range of entries so it starts at `to_index`, counted in the playlist without
+/// the moved entries
+pub fn move_entries(
+    playlist_id: String,
+    from_range: Range<usize>,
+    to_index: usize,
+    app_handle: &tauri::AppHandle,
+) -> Result<(), String> {
+    app_handle.state::<Library>().write(|library| {
+        let mut playlist = get_static_playlist(library, &playlist_id)?;
 
-    let duplicates_removed = original_count - unique_playlists.len();
+        move_range(&mut playlist.entries, from_range, to_index)?;
+        playlist.updated_at = now_secs();
 
-    if duplicates_removed > 0 {
-        metadata_cache.playlists = unique_playlists;
-        metadata::save_metadata_cache(&metadata_cache, app_handle)?;
+        library.save_playlist(&playlist)
+    })
+}
+
+/// Permanently reorder a playlist by one or more keys, e.g. album, then track
+pub fn sort_playlist(
+    playlist_id: String,
+    keys: Vec<SortKey>,
+    app_handle: &tauri::AppHandle,
+) -> Result<Playlist, String> {
+    if keys.is_empty() {
+        return Err("No sort keys given".to_string());
     }
 
-    Ok(())
+    app_handle.state::<Library>().write(|library| {
+        let mut playlist = get_static_playlist(library, &playlist_id)?;
+
+        metadata::sort_entries(&mut playlist.entries, &library.songs, &keys)?;
+        playlist.updated_at = now_secs();
+
+        library.save_playlist(&playlist)?;
+
+        Ok(playlist)
+    })
 }
 
-/// Sanitize a playlist name for use as a directory name
-fn sanitize_name(name: &str) -> String {
-    name.chars()
-        .map(|c| match c {
-            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
-            _ => c,
-        })
-        .collect()
+/// Reorder playlists to match a new order, which must list every playlist
+pub fn reorder_playlists(
+    playlist_ids: Vec<String>,
+    app_handle: &tauri::AppHandle,
+) -> Result<(), String> {
+    app_handle
+        .state::<Library>()
+        .write(|library| library.set_playlist_order(&playlist_ids))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(song_ids: &[&str]) -> Vec<PlaylistEntry> {
+        song_ids.iter().map(|id| PlaylistEntry::new(id)).collect()
+    }
+
+    fn song_ids(entries: &[PlaylistEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.song_id.as_str()).collect()
+    }
+
+    #[test]
+    fn moved_range_lands_at_index_among_the_rest() {
+        let mut list = entries(&["a", "b", "c", "d", "e"]);
+
+        move_range(&mut list, 1..3, 2).unwrap();
+        assert_eq!(song_ids(&list), ["a", "d", "b", "c", "e"]);
+
+        move_range(&mut list, 2..4, 0).unwrap();
+        assert_eq!(song_ids(&list), ["b", "c", "a", "d", "e"]);
+
+        // The end of the list is the number of entries left once the range is taken out
+        move_range(&mut list, 0..2, 3).unwrap();
+        assert_eq!(song_ids(&list), ["a", "d", "e", "b", "c"]);
+    }
+
+    #[test]
+    fn bad_ranges_and_positions_leave_entries_alone() {
+        let mut list = entries(&["a", "b", "c"]);
+
+        assert!(move_range(&mut list, 1..1, 0).is_err());
+        assert!(move_range(&mut list, 2..4, 0).is_err());
+        assert!(move_range(&mut list, 0..2, 2).is_err());
+        assert_eq!(song_ids(&list), ["a", "b", "c"]);
+    }
 }
@@ -0,0 +1,289 @@
+use crate::filesystem;
+use crate::models::{AppMetadata, Playlist};
+use crate::smart_playlist;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Sanitize a name for use as a file or directory name
+fn sanitize_name(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            _ => c,
+        })
+        .collect();
+
+    // Leading dots would hide the entry, and Windows drops trailing dots and spaces
+    let trimmed = sanitized
+        .trim_start_matches('.')
+        .trim_end_matches(['.', ' '])
+        .trim_start();
+
+    if trimmed.is_empty() {
+        "Untitled".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// Folder name for each playlist, keyed by playlist ID. Folders are named after the
+/// playlist; playlists sharing a name get " (2)", " (3)", ... in display order.
+pub fn dir_names(library: &AppMetadata) -> HashMap<String, String> {
+    let mut taken = HashSet::new();
+    let mut names = HashMap::new();
+
+    for playlist in &library.playlists {
+        let base = sanitize_name(&playlist.name);
+        let mut name = base.clone();
+        let mut n = 2;
+
+        // Compare case-insensitively, since macOS and Windows file systems do
+        while !taken.insert(name.to_lowercase()) {
+            name = format!("{} ({})", base, n);
+            n += 1;
+        }
+
+        names.insert(playlist.id.clone(), name);
+    }
+
+    names
+}
+
+/// Links a playlist's folder should hold: link name and the song file it points at.
+/// Names start with the entry's position, so repeats and same-named files can't collide.
+fn expected_links(
+    playlist: &Playlist,
+    library: &AppMetadata,
+    all_songs_dir: &Path,
+) -> Result<HashMap<String, PathBuf>, String> {
+    let playlist = smart_playlist::resolve(playlist.clone(), &library.songs)?;
+    let width = playlist.entries.len().to_string().len().max(3);
+
+    let mut links = HashMap::new();
+    for (index, entry) in playlist.entries.iter().enumerate() {
+        let song = match library.find_song(&entry.song_id) {
+            Some(song) => song,
+            None => continue,
+        };
+
+        // A link to a missing file would only be reported as broken
+        let target = all_songs_dir.join(&song.file_path);
+        if !target.exists() {
+            continue;
+        }
+
+        let extension = Path::new(&song.file_path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("mp3")
+            .to_lowercase();
+        let name = format!(
+            "{:0width$} - {}.{}",
+            index + 1,
+            sanitize_name(&format!("{} - {}", song.artist, song.title)),
+            extension,
+            width = width
+        );
+
+        links.insert(name, target);
+    }
+
+    Ok(links)
+}
+
+/// Check whether an existing link still points at the right file
+fn link_matches(link_path: &Path, target: &Path) -> bool {
+    // Windows mirrors use hard links, which can't be followed back to their target
+    #[cfg(unix)]
+    return fs::read_link(link_path)
+        .map(|t| t == target)
+        .unwrap_or(false)
+        && link_path.exists();
+
+    #[cfg(not(unix))]
+    {
+        let _ = target;
+        link_path.exists()
+    }
+}
+
+/// Find entries in a playlist folder that don't belong there
+fn stale_links(dir: &Path, expected: &HashMap<String, PathBuf>) -> Result<Vec<String>, String> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| format!("Failed to read playlist directory: {}", e))?;
+
+    Ok(entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|name| match expected.get(name) {
+            Some(target) => !link_matches(&dir.join(name), target),
+            None => true,
+        })
+        .collect())
+}
+
+/// Check for a directory, without following links
+fn is_real_dir(entry: &fs::DirEntry) -> bool {
+    entry.file_type().map(|t| t.is_dir()).unwrap_or(false)
+}
+
+/// Find everything under music/playlists that doesn't match the library: folders that
+/// aren't a playlist's and links that are broken, misnamed or point at the wrong song.
+/// Paths are relative to music/playlists.
+pub fn find_stale(library: &AppMetadata, music_dir: &Path) -> Result<Vec<String>, String> {
+    let playlists_dir = music_dir.join("playlists");
+    let all_songs_dir = music_dir.join("all_songs");
+    if !playlists_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let names = dir_names(library);
+    let playlists_by_dir: HashMap<&str, &Playlist> = library
+        .playlists
+        .iter()
+        .map(|p| (names[&p.id].as_str(), p))
+        .collect();
+
+    let entries = fs::read_dir(&playlists_dir)
+        .map_err(|e| format!("Failed to read playlists directory: {}", e))?;
+
+    let mut stale = Vec::new();
+    for entry in entries.filter_map(|e| e.ok()) {
+        let dir_name = entry.file_name().to_string_lossy().to_string();
+
+        let playlist = match playlists_by_dir.get(dir_name.as_str()) {
+            Some(playlist) if is_real_dir(&entry) => playlist,
+            _ => {
+                stale.push(dir_name);
+                continue;
+            }
+        };
+
+        let expected = expected_links(playlist, library, &all_songs_dir)?;
+        for link in stale_links(&entry.path(), &expected)? {
+            stale.push(format!("{}/{}", dir_name, link));
+        }
+    }
+
+    stale.sort();
+    Ok(stale)
+}
+
+/// Remove a file, link or directory without following links
+fn remove_entry(path: &Path) -> Result<(), String> {
+    let is_dir = path.symlink_metadata().map(|m| m.is_dir()).unwrap_or(false);
+    let result = if is_dir {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+
+    result.map_err(|e| format!("Failed to remove {}: {}", path.display(), e))
+}
+
+/// Bring music/playlists in line with the library. Folders that don't belong to a playlist
+/// are always removed; the contents of the given playlists (all of them if `playlist_ids`
+/// is None), and of any playlist whose folder is missing, are rebuilt.
+pub fn sync(
+    library: &AppMetadata,
+    music_dir: &Path,
+    playlist_ids: Option<&HashSet<String>>,
+) -> Result<(), String> {
+    let playlists_dir = music_dir.join("playlists");
+    let all_songs_dir = music_dir.join("all_songs");
+    fs::create_dir_all(&playlists_dir)
+        .map_err(|e| format!("Failed to create playlists directory: {}", e))?;
+
+    let names = dir_names(library);
+    let expected_dirs: HashSet<&str> = names.values().map(|n| n.as_str()).collect();
+
+    let entries = fs::read_dir(&playlists_dir)
+        .map_err(|e| format!("Failed to read playlists directory: {}", e))?;
+    for entry in entries.filter_map(|e| e.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !expected_dirs.contains(name.as_str()) || !is_real_dir(&entry) {
+            remove_entry(&entry.path())?;
+        }
+    }
+
+    for playlist in &library.playlists {
+        let dir = playlists_dir.join(&names[&playlist.id]);
+        let in_scope = match playlist_ids {
+            Some(ids) => ids.contains(&playlist.id),
+            None => true,
+        };
+        if !in_scope && dir.is_dir() {
+            continue;
+        }
+
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create playlist directory: {}", e))?;
+
+        let expected = expected_links(playlist, library, &all_songs_dir)?;
+        for stale in stale_links(&dir, &expected)? {
+            remove_entry(&dir.join(stale))?;
+        }
+
+        for (name, target) in &expected {
+            let link_path = dir.join(name);
+            if link_path.symlink_metadata().is_err() {
+                filesystem::create_link(target, &link_path)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::playlist;
+
+    fn library(names: &[(&str, &str)]) -> AppMetadata {
+        let playlists = names
+            .iter()
+            .map(|(id, name)| Playlist {
+                name: name.to_string(),
+                ..playlist(id, &[])
+            })
+            .collect();
+
+        AppMetadata {
+            playlists,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn names_are_made_safe_for_the_file_system() {
+        let names = dir_names(&library(&[
+            ("slash", "AC/DC: Live?"),
+            ("dots", "..hidden. "),
+            ("blank", "   "),
+        ]));
+
+        assert_eq!(names["slash"], "AC_DC_ Live_");
+        assert_eq!(names["dots"], "hidden");
+        assert_eq!(names["blank"], "Untitled");
+    }
+
+    #[test]
+    fn shared_names_are_numbered_in_display_order() {
+        let names = dir_names(&library(&[
+            ("first", "Mix"),
+            ("second", "mix"),
+            ("third", "Mix"),
+            ("other", "Mix (2) "),
+        ]));
+
+        assert_eq!(names["first"], "Mix");
+        assert_eq!(names["second"], "mix (2)");
+        assert_eq!(names["third"], "Mix (3)");
+        // Sanitizing can land on a name already given out
+        assert_eq!(names["other"], "Mix (2) (2)");
+    }
+}
@@ -0,0 +1,296 @@
+use crate::models::{Song, SongMatch};
+use crate::search_query::{self, Term, TermKind};
+use crate::smart_playlist;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// How much a match in each field counts towards a song's score
+const TITLE_WEIGHT: f32 = 3.0;
+const ARTIST_WEIGHT: f32 = 2.0;
+const ALBUM_WEIGHT: f32 = 1.0;
+
+/// Extra score, as a share of the field's weight, when a field contains the whole query
+const PHRASE_BONUS: f32 = 0.5;
+
+/// Fold text for matching: compatibility forms like full-width letters become their
+/// plain equivalents, accents are stripped and case is folded, so "ＢＥＹＯＮＣＥ" and
+/// "Beyonce\u{301}" both match "beyoncé"
+pub fn fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.to_lowercase().nfkd() {
+        match c {
+            // Only accents; marks like dakuten in kana or Indic vowel signs make another letter
+            _ if is_combining_mark(c) && folded.chars().last().is_some_and(has_accents) => {}
+            // Case folds that lowercasing alone misses
+            'ß' => folded.push_str("ss"),
+            'ς' => folded.push('σ'),
+            _ => folded.push(c),
+        }
+    }
+
+    // Put back together the letters whose marks were kept, e.g. が
+    folded.nfc().collect()
+}
+
+/// Whether a letter is Latin, Greek or Cyrillic, whose combining marks are accents
+fn has_accents(c: char) -> bool {
+    matches!(
+        c,
+        '\u{0}'..='\u{24F}'
+            | '\u{370}'..='\u{3FF}'
+            | '\u{400}'..='\u{4FF}'
+            | '\u{1E00}'..='\u{1EFF}'
+            | '\u{1F00}'..='\u{1FFF}'
+    )
+}
+
+/// Split folded text into words, dropping punctuation
+fn words(text: &str) -> Vec<Vec<char>> {
+    text.split(|c: char| !c.is_alphanumeric() && !is_combining_mark(c))
+        .filter(|w| !w.is_empty())
+        .map(|w| w.chars().collect())
+        .collect()
+}
+
+/// Number of single-character edits needed to turn one word into another
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+/// Typos tolerated in a query word; short words must match exactly
+fn allowed_typos(length: usize) -> usize {
+    match length {
+        0..=3 => 0,
+        4..=6 => 1,
+        _ => 2,
+    }
+}
+
+/// How well a query word matches a word in a field, from 0 (not at all) to 1 (exactly).
+/// Prefixes score highly, since the query is usually still being typed.
+fn word_score(query: &[char], word: &[char]) -> f32 {
+    if query == word {
+        return 1.0;
+    }
+    if word.starts_with(query) {
+        return 0.9;
+    }
+    if word.windows(query.len()).any(|w| w == query) {
+        return 0.6;
+    }
+
+    let allowed = allowed_typos(query.len());
+    if allowed == 0 {
+        return 0.0;
+    }
+
+    // Also compare with the start of the word, so a typo in a partly typed word still matches
+    let whole = edit_distance(query, word);
+    let start = edit_distance(query, &word[..query.len().min(word.len())]);
+    if whole <= allowed {
+        0.5 - 0.15 * (whole - 1) as f32
+    } else if start <= allowed {
+        0.4 - 0.15 * (start - 1) as f32
+    } else {
+        0.0
+    }
+}
+
+/// A song's searchable fields, folded and split into words once per search.
+/// Technical properties are left out, since short words like "mp" would match nearly
+/// every song; they're searched with field terms like `codec:` instead.
+struct SearchText {
+    fields: [(String, f32); 3], // Title, artist and album, with weights
+    words: Vec<Vec<Vec<char>>>,
+}
+
+impl SearchText {
+    fn new(song: &Song) -> Self {
+        let fields = [
+            (fold(&song.title), TITLE_WEIGHT),
+            (fold(&song.artist), ARTIST_WEIGHT),
+            (fold(&song.album), ALBUM_WEIGHT),
+        ];
+        let words = fields.iter().map(|(text, _)| words(text)).collect();
+
+        SearchText { fields, words }
+    }
+
+    /// Best weighted score of a query word in any field
+    fn word_score(&self, query_word: &[char]) -> f32 {
+        self.fields
+            .iter()
+            .zip(&self.words)
+            .map(|((_, weight), words)| {
+                let best_word = words
+                    .iter()
+                    .map(|word| word_score(query_word, word))
+                    .fold(0.0, f32::max);
+                best_word * weight
+            })
+            .fold(0.0, f32::max)
+    }
+
+    /// Check for a word starting with the query word, without allowing typos
+    fn has_word(&self, query_word: &[char]) -> bool {
+        self.words
+            .iter()
+            .flatten()
+            .any(|word| word.starts_with(query_word))
+    }
+
+    /// Weight of the best field containing a phrase
+    fn phrase_score(&self, phrase: &str) -> Option<f32> {
+        self.fields
+            .iter()
+            .filter(|(text, _)| text.contains(phrase))
+            .map(|(_, weight)| *weight)
+            .reduce(f32::max)
+    }
+}
+
+/// Check one term against a song, ignoring negation. Returns whether it matched and the
+/// score it adds.
+fn match_term(song: &Song, text: &SearchText, kind: &TermKind, negated: bool) -> (bool, f32) {
+    match kind {
+        TermKind::Word(word) => {
+            let query_words = words(word);
+
+            // Excluding is strict, so a typo can't hide songs the user wanted
+            if negated {
+                let matched =
+                    !query_words.is_empty() && query_words.iter().all(|w| text.has_word(w));
+                return (matched, 0.0);
+            }
+
+            let scores: Vec<f32> = query_words.iter().map(|w| text.word_score(w)).collect();
+            (scores.iter().all(|s| *s > 0.0), scores.iter().sum())
+        }
+        TermKind::Phrase(phrase) => match text.phrase_score(phrase) {
+            Some(score) => (true, score),
+            None => (false, 0.0),
+        },
+        TermKind::Text { field, value } => {
+            let matched = smart_playlist::text_value(song, *field)
+                .map(|v| fold(v).contains(value.as_str()))
+                .unwrap_or(false);
+            (matched, 0.0)
+        }
+        TermKind::Number { field, op, value } => {
+            // Songs missing the field don't match
+            let matched = smart_playlist::number_value(song, *field)
+                .map(|v| op.matches(v, *value))
+                .unwrap_or(false);
+            (matched, 0.0)
+        }
+    }
+}
+
+/// Score a song against one OR branch, or None if it doesn't match every term
+fn score_branch(song: &Song, text: &SearchText, terms: &[Term]) -> Option<f32> {
+    let mut score = 0.0;
+    for term in terms {
+        let (matched, term_score) = match_term(song, text, &term.kind, term.negated);
+        if matched == term.negated {
+            return None;
+        }
+        score += term_score;
+    }
+
+    // Reward songs where the words appear together, as typed
+    let free_words: Vec<&str> = terms
+        .iter()
+        .filter(|t| !t.negated)
+        .filter_map(|t| match &t.kind {
+            TermKind::Word(word) => Some(word.as_str()),
+            _ => None,
+        })
+        .collect();
+    if free_words.len() > 1 {
+        let phrase = free_words.join(" ");
+        for (field, weight) in &text.fields {
+            if field.contains(&phrase) {
+                score += weight * PHRASE_BONUS;
+            }
+        }
+    }
+
+    Some(score)
+}
+
+/// Search songs, tolerating typos in plain words. Queries can also use `field:value`,
+/// comparisons like `year:>=2001` or `duration:<4m`, "quoted phrases", `-` to exclude
+/// and OR (see `search_query::parse`). Results are ranked best first; an empty query
+/// matches every song with a score of 0.
+pub fn search_songs(query: &str, songs: &[Song]) -> Result<Vec<SongMatch>, String> {
+    let query = search_query::parse(query).map_err(|e| e.to_string())?;
+
+    let mut matches: Vec<SongMatch> = songs
+        .iter()
+        .filter_map(|song| {
+            let score = if query.branches.is_empty() {
+                0.0
+            } else {
+                let text = SearchText::new(song);
+                query
+                    .branches
+                    .iter()
+                    .filter_map(|terms| score_branch(song, &text, terms))
+                    .reduce(f32::max)?
+            };
+
+            Some(SongMatch {
+                song: song.clone(),
+                score,
+            })
+        })
+        .collect();
+
+    // Stable, so equally good matches stay in library order
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{song, song_with};
+    use serde_json::json;
+
+    #[test]
+    fn folds_accents_width_and_case() {
+        assert_eq!(fold("Beyoncé"), "beyonce");
+        assert_eq!(fold("ＢＥＹＯＮＣＥ"), "beyonce");
+        assert_eq!(fold("Beyonce\u{301}"), "beyonce");
+        assert_eq!(fold("Ῥόδος"), "ροδοσ");
+    }
+
+    #[test]
+    fn keeps_marks_that_make_another_letter() {
+        assert_eq!(fold("が"), "が");
+        assert_ne!(fold("が"), fold("か"));
+        assert_eq!(fold("हिन्दी"), "हिन्दी");
+        assert_eq!(words(&fold("हिन्दी गाना")).len(), 2);
+    }
+
+    #[test]
+    fn plain_query_finds_accented_title() {
+        let songs = [song_with("b", json!({ "title": "Beyoncé" })), song("Halo")];
+        let matches = search_songs("beyonce", &songs).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].song.title, "Beyoncé");
+    }
+}
@@ -0,0 +1,413 @@
+use crate::models::{NumberField, TextField};
+use crate::search;
+use std::fmt;
+
+/// A parsed search query: songs match if they match any branch (OR), and match a branch
+/// if they match all of its terms
+#[derive(Debug, Clone)]
+pub struct Query {
+    pub branches: Vec<Vec<Term>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Term {
+    pub negated: bool,
+    pub kind: TermKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum TermKind {
+    /// Fuzzy matched against title, artist and album
+    Word(String),
+    /// Quoted; must appear as typed in the title, artist or album
+    Phrase(String),
+    /// e.g. genre:house, matched as a substring
+    Text { field: TextField, value: String },
+    /// e.g. year:>=2001
+    Number {
+        field: NumberField,
+        op: Comparison,
+        value: f64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    pub fn matches(self, actual: f64, expected: f64) -> bool {
+        match self {
+            Comparison::Equal => actual == expected,
+            Comparison::Less => actual < expected,
+            Comparison::LessOrEqual => actual <= expected,
+            Comparison::Greater => actual > expected,
+            Comparison::GreaterOrEqual => actual >= expected,
+        }
+    }
+}
+
+/// A query that couldn't be parsed. `position` counts characters from the start of the query.
+#[derive(Debug, Clone)]
+pub struct QueryError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+fn error(position: usize, message: impl Into<String>) -> QueryError {
+    QueryError {
+        position,
+        message: message.into(),
+    }
+}
+
+/// Fields that can be used as `field:value`
+enum Field {
+    Text(TextField),
+    Number(NumberField),
+}
+
+fn field_named(name: &str) -> Option<Field> {
+    let field = match name.to_lowercase().as_str() {
+        "title" => Field::Text(TextField::Title),
+        "artist" => Field::Text(TextField::Artist),
+        "album" => Field::Text(TextField::Album),
+        "genre" => Field::Text(TextField::Genre),
+        "codec" => Field::Text(TextField::Codec),
+        "year" => Field::Number(NumberField::Year),
+        "duration" | "length" => Field::Number(NumberField::DurationSecs),
+        "bitrate" => Field::Number(NumberField::BitrateKbps),
+        _ => return None,
+    };
+
+    Some(field)
+}
+
+/// Parse a duration such as "240", "240s", "4m", "3m30s", "1h" or "3:30" into seconds
+fn parse_duration(value: &str) -> Option<f64> {
+    if let Some((minutes, seconds)) = value.split_once(':') {
+        let minutes: u32 = minutes.parse().ok()?;
+        let seconds: u32 = seconds.parse().ok()?;
+        return (seconds < 60).then_some(f64::from(minutes) * 60.0 + f64::from(seconds));
+    }
+
+    let mut total = 0.0;
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+
+        let unit = match c {
+            'h' => 3600.0,
+            'm' => 60.0,
+            's' => 1.0,
+            _ => return None,
+        };
+        total += number.parse::<f64>().ok()? * unit;
+        number.clear();
+    }
+
+    // A bare number is in seconds
+    if !number.is_empty() {
+        total += number.parse::<f64>().ok()?;
+    }
+
+    total.is_finite().then_some(total)
+}
+
+/// Parse the value of a numeric field, e.g. ">=2001", "<4m" or "320k"
+fn parse_comparison(
+    field: NumberField,
+    value: &str,
+    position: usize,
+) -> Result<(Comparison, f64), QueryError> {
+    let (op, number) = [
+        (">=", Comparison::GreaterOrEqual),
+        ("<=", Comparison::LessOrEqual),
+        (">", Comparison::Greater),
+        ("<", Comparison::Less),
+        ("=", Comparison::Equal),
+    ]
+    .iter()
+    .find_map(|(prefix, op)| value.strip_prefix(prefix).map(|rest| (*op, rest)))
+    .unwrap_or((Comparison::Equal, value));
+
+    let number_position = position + value.chars().count() - number.chars().count();
+    if number.is_empty() {
+        return Err(error(number_position, "Expected a number"));
+    }
+
+    let lower = number.to_lowercase();
+    let parsed = match field {
+        NumberField::DurationSecs => parse_duration(&lower),
+        NumberField::BitrateKbps => lower
+            .trim_end_matches("kbps")
+            .trim_end_matches('k')
+            .parse()
+            .ok(),
+        _ => lower.parse().ok(),
+    };
+
+    match parsed {
+        Some(parsed) => Ok((op, parsed)),
+        None if field == NumberField::DurationSecs => Err(error(
+            number_position,
+            format!(
+                "Invalid duration '{}', expected e.g. 4m, 3m30s or 3:30",
+                number
+            ),
+        )),
+        None => Err(error(
+            number_position,
+            format!("Invalid number '{}'", number),
+        )),
+    }
+}
+
+/// Reads the query a character at a time, tracking the position for errors
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    /// Read a quoted phrase, starting at the opening quote
+    fn quoted(&mut self) -> Result<String, QueryError> {
+        let start = self.position;
+        self.position += 1;
+
+        let mut phrase = String::new();
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.position += 1;
+                    return Ok(phrase);
+                }
+                Some(c) => {
+                    phrase.push(c);
+                    self.position += 1;
+                }
+                None => return Err(error(start, "Unterminated quote")),
+            }
+        }
+    }
+
+    /// Read an unquoted word, up to whitespace or a quote
+    fn bare(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || c == '"' {
+                break;
+            }
+            word.push(c);
+            self.position += 1;
+        }
+        word
+    }
+}
+
+/// A term, or the OR between two branches
+enum Token {
+    Term(Term),
+    Or(usize),
+}
+
+/// Parse the term starting at the parser's position
+fn parse_token(parser: &mut Parser) -> Result<Token, QueryError> {
+    let start = parser.position;
+
+    // A leading '-' negates, unless it's all there is, e.g. "AC - DC"
+    let negated = parser.peek() == Some('-')
+        && parser
+            .chars
+            .get(start + 1)
+            .is_some_and(|c| !c.is_whitespace());
+    if negated {
+        parser.position += 1;
+    }
+
+    if parser.peek() == Some('"') {
+        let phrase = parser.quoted()?;
+        if phrase.trim().is_empty() {
+            return Err(error(start, "Empty quotes"));
+        }
+        return Ok(Token::Term(Term {
+            negated,
+            kind: TermKind::Phrase(search::fold(&phrase)),
+        }));
+    }
+
+    let word_start = parser.position;
+    let word = parser.bare();
+    if word == "OR" && !negated {
+        return Ok(Token::Or(start));
+    }
+
+    // Only known field names make a field, so e.g. "10:30" or "Re:" is searched as a word
+    let field = word
+        .split_once(':')
+        .and_then(|(name, value)| Some((name, value, field_named(name)?)));
+
+    let kind = match field {
+        Some((name, value, field)) => {
+            let value_start = word_start + name.chars().count() + 1;
+            let value = if value.is_empty() && parser.peek() == Some('"') {
+                parser.quoted()?
+            } else {
+                value.to_string()
+            };
+            if value.trim().is_empty() {
+                return Err(error(
+                    value_start,
+                    format!("Expected a value after '{}:'", name),
+                ));
+            }
+
+            match field {
+                Field::Text(field) => TermKind::Text {
+                    field,
+                    value: search::fold(&value),
+                },
+                Field::Number(field) => {
+                    let (op, value) = parse_comparison(field, &value, value_start)?;
+                    TermKind::Number { field, op, value }
+                }
+            }
+        }
+        None => TermKind::Word(search::fold(&word)),
+    };
+
+    Ok(Token::Term(Term { negated, kind }))
+}
+
+/// Parse a search query such as `artist:"daft punk" year:>=2001 duration:<4m -live`.
+/// Terms are ANDed together; OR (in capitals) separates alternatives.
+pub fn parse(query: &str) -> Result<Query, QueryError> {
+    let mut parser = Parser {
+        chars: query.chars().collect(),
+        position: 0,
+    };
+
+    let mut branches = Vec::new();
+    let mut branch = Vec::new();
+    let mut last_or = None;
+
+    loop {
+        parser.skip_whitespace();
+        if parser.peek().is_none() {
+            break;
+        }
+
+        match parse_token(&mut parser)? {
+            Token::Term(term) => branch.push(term),
+            Token::Or(position) => {
+                if branch.is_empty() {
+                    return Err(error(position, "Expected a search term before OR"));
+                }
+                branches.push(std::mem::take(&mut branch));
+                last_or = Some(position);
+            }
+        }
+    }
+
+    if branch.is_empty() {
+        if let Some(position) = last_or {
+            return Err(error(position, "Expected a search term after OR"));
+        }
+    } else {
+        branches.push(branch);
+    }
+
+    Ok(Query { branches })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_at(query: &str) -> usize {
+        parse(query).unwrap_err().position
+    }
+
+    #[test]
+    fn parses_fields_comparisons_and_negation() {
+        let query = parse(r#"artist:"daft punk" year:>=2001 duration:<4m -live"#).unwrap();
+        assert_eq!(query.branches.len(), 1);
+
+        let terms = &query.branches[0];
+        assert!(matches!(
+            &terms[0].kind,
+            TermKind::Text { field: TextField::Artist, value } if value == "daft punk"
+        ));
+        assert!(matches!(
+            terms[1].kind,
+            TermKind::Number {
+                field: NumberField::Year,
+                op: Comparison::GreaterOrEqual,
+                value
+            } if value == 2001.0
+        ));
+        assert!(matches!(
+            terms[2].kind,
+            TermKind::Number {
+                field: NumberField::DurationSecs,
+                op: Comparison::Less,
+                value
+            } if value == 240.0
+        ));
+        assert!(terms[3].negated);
+        assert!(matches!(&terms[3].kind, TermKind::Word(word) if word == "live"));
+    }
+
+    #[test]
+    fn or_separates_branches() {
+        let query = parse("house OR techno deep").unwrap();
+        let lengths: Vec<usize> = query.branches.iter().map(Vec::len).collect();
+        assert_eq!(lengths, [1, 2]);
+    }
+
+    #[test]
+    fn unknown_field_names_are_words() {
+        let query = parse("Re: Stacks").unwrap();
+        let terms = &query.branches[0];
+        assert_eq!(terms.len(), 2);
+        assert!(terms.iter().all(|t| matches!(t.kind, TermKind::Word(_))));
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        assert_eq!(error_at(r#"live "daft punk"#), 5);
+        assert_eq!(error_at("year:>= live"), 7);
+        assert_eq!(error_at("OR live"), 0);
+        assert_eq!(error_at("live OR"), 5);
+    }
+
+    #[test]
+    fn huge_durations_dont_overflow() {
+        assert!(parse("duration:<99999999:00").is_ok());
+        assert!(parse(&format!("duration:<{}m", "9".repeat(400))).is_err());
+    }
+}
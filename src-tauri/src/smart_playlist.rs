@@ -0,0 +1,202 @@
+use crate::metadata;
+use crate::models::{
+    Condition, NumberField, Playlist, PlaylistEntry, Rule, RuleGroup, SmartCriteria, Song,
+    TextField,
+};
+use std::time::SystemTime;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Get a text field from a song, if it has one
+pub fn text_value(song: &Song, field: TextField) -> Option<&str> {
+    match field {
+        TextField::Title => Some(&song.title),
+        TextField::Artist => Some(&song.artist),
+        TextField::Album => Some(&song.album),
+        TextField::Genre => song.genre.as_deref(),
+        TextField::Codec => song.codec.as_deref(),
+    }
+}
+
+/// Get a numeric field from a song, if it has one
+pub fn number_value(song: &Song, field: NumberField) -> Option<f64> {
+    match field {
+        NumberField::Year => song.year.map(f64::from),
+        NumberField::DurationSecs => song.duration_secs.map(f64::from),
+        NumberField::BitrateKbps => song.bitrate_kbps.map(f64::from),
+    }
+}
+
+/// Check a single condition. Text comparisons ignore case; songs missing the field don't match.
+fn matches_condition(song: &Song, condition: &Condition, now: u64) -> bool {
+    let text = |field: TextField| text_value(song, field).map(|v| v.to_lowercase());
+
+    match condition {
+        Condition::Is { field, value } => text(*field) == Some(value.to_lowercase()),
+        Condition::IsNot { field, value } => text(*field)
+            .map(|v| v != value.to_lowercase())
+            .unwrap_or(false),
+        Condition::Contains { field, value } => text(*field)
+            .map(|v| v.contains(&value.to_lowercase()))
+            .unwrap_or(false),
+        Condition::NotContains { field, value } => text(*field)
+            .map(|v| !v.contains(&value.to_lowercase()))
+            .unwrap_or(false),
+        Condition::GreaterThan { field, value } => number_value(song, *field)
+            .map(|v| v > *value)
+            .unwrap_or(false),
+        Condition::LessThan { field, value } => number_value(song, *field)
+            .map(|v| v < *value)
+            .unwrap_or(false),
+        Condition::Between { field, min, max } => number_value(song, *field)
+            .map(|v| v >= *min && v <= *max)
+            .unwrap_or(false),
+        Condition::AddedInLastDays { days } => {
+            song.added_at > 0 && song.added_at >= now.saturating_sub(*days as u64 * SECS_PER_DAY)
+        }
+    }
+}
+
+/// Check a group of rules, all of which (AND) or any of which (OR) must match
+fn matches_group(song: &Song, group: &RuleGroup, now: u64) -> bool {
+    let matches = |rule: &Rule| match rule {
+        Rule::Group(group) => matches_group(song, group, now),
+        Rule::Condition(condition) => matches_condition(song, condition, now),
+    };
+
+    if group.match_all {
+        group.rules.iter().all(matches)
+    } else {
+        group.rules.iter().any(matches)
+    }
+}
+
+/// Check whether a change to a song could change a smart playlist's songs, their order or
+/// the links to them. `old` is None for a new song and `new` is None for a deleted one.
+pub fn affected_by(
+    criteria: &SmartCriteria,
+    old: Option<&Song>,
+    new: Option<&Song>,
+    affects_links: bool,
+) -> bool {
+    let now = now_secs();
+    let matches =
+        |song: Option<&Song>| song.is_some_and(|s| matches_group(s, &criteria.rules, now));
+
+    let (was_in, is_in) = (matches(old), matches(new));
+    if was_in != is_in {
+        return true;
+    }
+    if !is_in {
+        return false;
+    }
+
+    // Still in the playlist, but its link or its place in the order may have moved
+    let reordered = match (&criteria.sort_by, old, new) {
+        (Some(sort_by), Some(old), Some(new)) => !metadata::sorts_equal(sort_by, old, new),
+        _ => false,
+    };
+
+    affects_links || reordered
+}
+
+/// Check that criteria can be evaluated
+pub fn validate(criteria: &SmartCriteria) -> Result<(), String> {
+    if criteria.limit == Some(0) {
+        return Err("Limit must be greater than zero".to_string());
+    }
+
+    if let Some(sort_by) = &criteria.sort_by {
+        if !metadata::is_sort_field(sort_by) {
+            return Err(format!("Unknown sort field: {}", sort_by));
+        }
+    }
+
+    validate_group(&criteria.rules)
+}
+
+fn validate_group(group: &RuleGroup) -> Result<(), String> {
+    for rule in &group.rules {
+        match rule {
+            Rule::Group(group) => validate_group(group)?,
+            Rule::Condition(Condition::Between { min, max, .. }) if min > max => {
+                return Err(format!("Invalid range: {} is greater than {}", min, max));
+            }
+            Rule::Condition(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Find the songs matching the criteria, sorted and limited
+pub fn evaluate(criteria: &SmartCriteria, songs: &[Song]) -> Result<Vec<Song>, String> {
+    let now = now_secs();
+
+    let mut matched: Vec<Song> = songs
+        .iter()
+        .filter(|song| matches_group(song, &criteria.rules, now))
+        .cloned()
+        .collect();
+
+    if let Some(sort_by) = &criteria.sort_by {
+        metadata::sort_songs(&mut matched, sort_by, criteria.descending)?;
+    }
+
+    if let Some(limit) = criteria.limit {
+        matched.truncate(limit);
+    }
+
+    Ok(matched)
+}
+
+/// Fill in a smart playlist's songs from its rules. Other playlists are returned as they are.
+pub fn resolve(mut playlist: Playlist, songs: &[Song]) -> Result<Playlist, String> {
+    if let Some(criteria) = &playlist.smart {
+        // Each song matches at most once, so its ID doubles as the entry ID
+        playlist.entries = evaluate(criteria, songs)?
+            .into_iter()
+            .map(|song| PlaylistEntry {
+                id: song.id.clone(),
+                song_id: song.id,
+            })
+            .collect();
+    }
+
+    Ok(playlist)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::song_with;
+
+    fn song(genre: Option<&str>) -> Song {
+        song_with("song", serde_json::json!({ "genre": genre }))
+    }
+
+    #[test]
+    fn negative_conditions_skip_songs_missing_the_field() {
+        let is_not = Condition::IsNot {
+            field: TextField::Genre,
+            value: "House".to_string(),
+        };
+        let not_contains = Condition::NotContains {
+            field: TextField::Genre,
+            value: "house".to_string(),
+        };
+
+        for condition in [&is_not, &not_contains] {
+            assert!(matches_condition(&song(Some("Techno")), condition, 0));
+            assert!(!matches_condition(&song(Some("house")), condition, 0));
+            assert!(!matches_condition(&song(None), condition, 0));
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use crate::filesystem;
+use rusqlite::{Connection, OpenFlags};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// How many snapshots of the library to keep, taken at startup and before big changes
+const KEEP_SNAPSHOTS: usize = 5;
+
+/// Directory holding known-good copies of the library database
+fn snapshots_dir(app_data_path: &Path) -> PathBuf {
+    app_data_path.join("snapshots")
+}
+
+/// Milliseconds since the epoch, used to name and order copies
+fn timestamp_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
+
+/// Flush a directory so a rename inside it survives a crash
+fn sync_dir(dir: &Path) -> Result<(), String> {
+    // Directories can't be opened for syncing on Windows; NTFS renames are journaled anyway
+    #[cfg(unix)]
+    File::open(dir)
+        .and_then(|d| d.sync_all())
+        .map_err(|e| format!("Failed to sync directory: {}", e))?;
+
+    #[cfg(not(unix))]
+    let _ = dir;
+
+    Ok(())
+}
+
+/// Write a consistent copy of the database to `dest`.
+/// The copy goes to a temp file which is fsynced and then renamed into place,
+/// so `dest` is either the complete old file or the complete new one.
+pub fn write_database_copy(conn: &Connection, dest: &Path) -> Result<(), String> {
+    let dir = dest
+        .parent()
+        .ok_or_else(|| "Invalid database copy path".to_string())?;
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create backup directory: {}", e))?;
+
+    let temp_path = dest.with_extension("db.tmp");
+    if temp_path.exists() {
+        fs::remove_file(&temp_path)
+            .map_err(|e| format!("Failed to remove stale temp file: {}", e))?;
+    }
+
+    let temp_path_str = temp_path
+        .to_str()
+        .ok_or_else(|| "Invalid UTF-8 in path".to_string())?;
+
+    // VACUUM INTO includes changes still in the WAL, unlike copying the file
+    conn.execute("VACUUM INTO ?1", [temp_path_str])
+        .map_err(|e| format!("Failed to copy library database: {}", e))?;
+
+    File::open(&temp_path)
+        .and_then(|f| f.sync_all())
+        .map_err(|e| format!("Failed to sync library copy: {}", e))?;
+
+    fs::rename(&temp_path, dest).map_err(|e| format!("Failed to save library copy: {}", e))?;
+    sync_dir(dir)?;
+
+    Ok(())
+}
+
+/// Check whether a database file opens, passes SQLite's quick integrity check and holds
+/// a library. SQLite opens an empty or truncated file as an empty database, so that
+/// alone doesn't prove anything survived.
+fn is_valid_database(path: &Path) -> bool {
+    // Open read-write so a WAL left behind by a crash is recovered rather than rejected
+    let conn = match Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE) {
+        Ok(conn) => conn,
+        Err(_) => return false,
+    };
+
+    let intact = conn
+        .query_row("PRAGMA quick_check", [], |row| row.get::<_, String>(0))
+        .map(|result| result == "ok")
+        .unwrap_or(false);
+
+    let has_library = conn
+        .query_row(
+            "SELECT COUNT(*) FROM sqlite_master
+             WHERE type = 'table' AND name IN ('songs', 'playlists')",
+            [],
+            |row| row.get::<_, u32>(0),
+        )
+        .map(|count| count == 2)
+        .unwrap_or(false);
+
+    // Databases from before schema versioning are at version 0, so only the tables count
+    intact && has_library
+}
+
+/// Check whether a database file has nothing in it, as when it was created but never set up
+fn is_empty_database(path: &Path) -> bool {
+    Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .and_then(|conn| {
+            conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| {
+                row.get::<_, u32>(0)
+            })
+        })
+        .map(|count| count == 0)
+        .unwrap_or(false)
+}
+
+/// List snapshots, newest first
+fn list_snapshots(app_data_path: &Path) -> Result<Vec<PathBuf>, String> {
+    let dir = snapshots_dir(app_data_path);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let entries =
+        fs::read_dir(&dir).map_err(|e| format!("Failed to read snapshots directory: {}", e))?;
+
+    let mut snapshots: Vec<(u128, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let timestamp = path
+                .file_name()?
+                .to_str()?
+                .strip_prefix("library-")?
+                .strip_suffix(".db")?
+                .parse()
+                .ok()?;
+            Some((timestamp, path))
+        })
+        .collect();
+
+    snapshots.sort_by_key(|(timestamp, _)| std::cmp::Reverse(*timestamp));
+
+    Ok(snapshots.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Save a known-good copy of the library and drop all but the newest few
+pub fn take_snapshot(conn: &Connection, app_data_path: &Path) -> Result<(), String> {
+    let snapshot_path =
+        snapshots_dir(app_data_path).join(format!("library-{}.db", timestamp_millis()));
+
+    write_database_copy(conn, &snapshot_path)?;
+
+    for old in list_snapshots(app_data_path)?.iter().skip(KEEP_SNAPSHOTS) {
+        fs::remove_file(old).map_err(|e| format!("Failed to remove old snapshot: {}", e))?;
+    }
+
+    Ok(())
+}
+
+/// If the library database is corrupt, set it aside and restore the newest valid snapshot.
+/// Must run before the database is opened for normal use. Returns the snapshot restored, if any.
+pub fn recover_database(app_handle: &tauri::AppHandle) -> Result<Option<PathBuf>, String> {
+    let app_data_path = filesystem::get_app_data_path(app_handle)?;
+    recover_database_in(&app_data_path)
+}
+
+fn recover_database_in(app_data_path: &Path) -> Result<Option<PathBuf>, String> {
+    let db_path = app_data_path.join("library.db");
+
+    if !db_path.exists() || is_valid_database(&db_path) {
+        return Ok(None);
+    }
+
+    let snapshot = match list_snapshots(app_data_path)?
+        .into_iter()
+        .find(|path| is_valid_database(path))
+    {
+        Some(snapshot) => snapshot,
+        // With nothing to restore, an empty database is one that was never set up
+        None if is_empty_database(&db_path) => return Ok(None),
+        None => {
+            return Err(
+                "Library database is corrupt and no valid snapshot is available".to_string(),
+            )
+        }
+    };
+
+    // Keep the corrupt file and its journal around in case something can be salvaged
+    let suffix = format!("corrupt-{}", timestamp_millis());
+    for name in ["library.db", "library.db-wal", "library.db-shm"] {
+        let path = app_data_path.join(name);
+        if path.exists() {
+            fs::rename(&path, app_data_path.join(format!("{}.{}", name, suffix)))
+                .map_err(|e| format!("Failed to move corrupt library aside: {}", e))?;
+        }
+    }
+
+    // Copy through a temp file so a crash here can't leave a half-restored library
+    let temp_path = app_data_path.join("library.db.tmp");
+    fs::copy(&snapshot, &temp_path).map_err(|e| format!("Failed to restore snapshot: {}", e))?;
+    File::open(&temp_path)
+        .and_then(|f| f.sync_all())
+        .map_err(|e| format!("Failed to sync restored library: {}", e))?;
+    fs::rename(&temp_path, &db_path).map_err(|e| format!("Failed to restore snapshot: {}", e))?;
+    sync_dir(app_data_path)?;
+
+    Ok(Some(snapshot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database;
+    use crate::migrations;
+    use crate::test_fixtures::playlist;
+
+    /// Write a snapshot of a library with the given playlists
+    fn write_snapshot(app_data_path: &Path, timestamp: u128, playlist_names: &[&str]) {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::run_migrations(&mut conn, &app_data_path.join("backups")).unwrap();
+        for name in playlist_names {
+            database::save_playlist(&conn, &playlist(name, &[])).unwrap();
+        }
+
+        let path = snapshots_dir(app_data_path).join(format!("library-{}.db", timestamp));
+        write_database_copy(&conn, &path).unwrap();
+    }
+
+    #[test]
+    fn empty_database_is_restored_from_newest_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        write_snapshot(dir.path(), 1000, &["old"]);
+        write_snapshot(dir.path(), 2000, &["old", "new"]);
+
+        // A crash can leave the file truncated to nothing
+        let db_path = dir.path().join("library.db");
+        File::create(&db_path).unwrap();
+
+        let restored = recover_database_in(dir.path()).unwrap();
+        assert_eq!(
+            restored,
+            Some(snapshots_dir(dir.path()).join("library-2000.db"))
+        );
+
+        let conn = Connection::open(&db_path).unwrap();
+        let names: Vec<String> = database::load_playlists(&conn)
+            .unwrap()
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        assert_eq!(names, ["old", "new"]);
+    }
+
+    #[test]
+    fn unversioned_database_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        write_snapshot(dir.path(), 1000, &["snapshot"]);
+
+        // Libraries from before schema versioning have the tables at version 0
+        let db_path = dir.path().join("library.db");
+        let conn = Connection::open(&db_path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE songs (id TEXT PRIMARY KEY);
+             CREATE TABLE playlists (id TEXT PRIMARY KEY);",
+        )
+        .unwrap();
+        drop(conn);
+
+        assert_eq!(recover_database_in(dir.path()).unwrap(), None);
+    }
+
+    #[test]
+    fn empty_database_without_snapshots_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        File::create(dir.path().join("library.db")).unwrap();
+
+        assert_eq!(recover_database_in(dir.path()).unwrap(), None);
+    }
+}
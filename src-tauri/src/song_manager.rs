@@ -0,0 +1,109 @@
+use crate::fingerprint;
+use crate::library::{Library, Writer};
+use crate::models::Song;
+use crate::trash;
+use crate::waveform;
+use std::collections::HashSet;
+use std::fs;
+use tauri::Manager;
+
+/// Delete a song's audio file and any cached analysis no other song still needs, once
+/// the change is saved. Call this after the song's row is deleted so it isn't counted
+/// as a remaining user.
+pub fn delete_song_files(song: &Song, library: &mut Writer) -> Result<(), String> {
+    let data_dir = library.data_dir().to_path_buf();
+    let absolute_path = data_dir.join("music/all_songs").join(&song.file_path);
+
+    // Remove cached analysis unless another copy of the same file remains
+    let unused_hash = song
+        .file_hash
+        .clone()
+        .filter(|file_hash| !library.file_hash_in_use(file_hash, &song.id));
+
+    library.after_commit(move || {
+        // Delete the actual song file
+        if absolute_path.exists() {
+            fs::remove_file(&absolute_path)
+                .map_err(|e| format!("Failed to delete song file: {}", e))?;
+        }
+
+        if let Some(file_hash) = unused_hash {
+            waveform::remove_cached_waveform(&file_hash, &data_dir)?;
+            fingerprint::remove_cached_fingerprint(&file_hash, &data_dir)?;
+        }
+
+        Ok(())
+    });
+
+    Ok(())
+}
+
+/// Move a song to the trash, removing it from the library and all playlists
+pub fn delete_song(song_id: String, app_handle: &tauri::AppHandle) -> Result<(), String> {
+    app_handle
+        .state::<Library>()
+        .write(|library| trash::trash_song(&song_id, library))
+}
+
+/// Merge duplicate songs into one, rewiring playlists
+pub fn merge_songs(
+    keep_id: String,
+    remove_ids: Vec<String>,
+    app_handle: &tauri::AppHandle,
+) -> Result<Song, String> {
+    if remove_ids.contains(&keep_id) {
+        return Err("Cannot merge a song into itself".to_string());
+    }
+
+    // A song listed twice would be removed twice
+    let mut remove_ids = remove_ids;
+    let mut seen = HashSet::new();
+    remove_ids.retain(|id| seen.insert(id.clone()));
+
+    app_handle.state::<Library>().write(|library| {
+        let kept = library.get_song(&keep_id)?.clone();
+        let removed = remove_ids
+            .iter()
+            .map(|id| library.get_song(id).cloned())
+            .collect::<Result<Vec<Song>, String>>()?;
+
+        // Point playlists at the kept song, without adding it to playlists that already have it
+        let mut affected_playlists = Vec::new();
+        for id in &remove_ids {
+            for playlist_id in library.playlists_containing(id) {
+                if !affected_playlists.contains(&playlist_id) {
+                    affected_playlists.push(playlist_id);
+                }
+            }
+        }
+
+        for playlist_id in affected_playlists {
+            let mut playlist = library.get_playlist(&playlist_id)?.clone();
+            let mut has_kept = playlist.contains_song(&keep_id);
+            let mut entries = Vec::with_capacity(playlist.entries.len());
+
+            for mut entry in playlist.entries {
+                if remove_ids.contains(&entry.song_id) {
+                    if has_kept {
+                        continue;
+                    }
+                    entry.song_id = keep_id.clone();
+                    has_kept = true;
+                }
+
+                entries.push(entry);
+            }
+
+            playlist.entries = entries;
+            library.save_playlist(&playlist)?;
+        }
+
+        // Drop the others from the library
+        for song in &removed {
+            library.delete_song(&song.id)?;
+            delete_song_files(song, library)?;
+        }
+
+        Ok(kept)
+    })
+}
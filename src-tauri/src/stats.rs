@@ -0,0 +1,180 @@
+use crate::browse;
+use crate::filesystem;
+use crate::library::Library;
+use crate::models::{CountEntry, LibraryStats, Song};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use tauri::Manager;
+
+/// How many of the largest files to include
+const BIGGEST_FILES_LIMIT: usize = 10;
+
+/// Label used when a song has no value for a field
+const UNKNOWN: &str = "Unknown";
+
+/// Count songs per key, highest count first, ties broken by name
+fn count_by<F>(songs: &[Song], key: F) -> Vec<CountEntry>
+where
+    F: Fn(&Song) -> String,
+{
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for song in songs {
+        *counts.entry(key(song)).or_default() += 1;
+    }
+
+    let entries = counts
+        .into_iter()
+        .map(|(name, count)| CountEntry {
+            name,
+            artist: None,
+            count,
+        })
+        .collect();
+
+    sort_counts(entries)
+}
+
+/// Count songs per album. Albums are told apart by artist as well as title,
+/// since many artists have a "Greatest Hits".
+fn count_albums(songs: &[Song]) -> Vec<CountEntry> {
+    let mut counts: HashMap<(String, String), usize> = HashMap::new();
+    for song in songs {
+        let key = (song.album.clone(), browse::album_artist(song).to_string());
+        *counts.entry(key).or_default() += 1;
+    }
+
+    let entries = counts
+        .into_iter()
+        .map(|((name, artist), count)| CountEntry {
+            name,
+            artist: Some(artist),
+            count,
+        })
+        .collect();
+
+    sort_counts(entries)
+}
+
+/// Sort counts highest first, ties broken by name
+fn sort_counts(mut entries: Vec<CountEntry>) -> Vec<CountEntry> {
+    entries.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| a.name.cmp(&b.name))
+            .then_with(|| a.artist.cmp(&b.artist))
+    });
+
+    entries
+}
+
+/// Get a song's format, preferring the codec read from the stream over the file extension
+fn format_of(song: &Song) -> String {
+    song.codec.clone().unwrap_or_else(|| {
+        Path::new(&song.file_path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_uppercase())
+            .unwrap_or_else(|| UNKNOWN.to_string())
+    })
+}
+
+/// Compute statistics over the whole library
+pub fn get_library_stats(app_handle: &tauri::AppHandle) -> Result<LibraryStats, String> {
+    let songs = app_handle
+        .state::<Library>()
+        .read(|library| Ok(library.songs.clone()))?;
+
+    let all_songs_dir = filesystem::get_app_data_path(app_handle)?.join("music/all_songs");
+
+    // Use the size on disk, falling back to the recorded size if the file is gone
+    let sizes: Vec<u64> = songs
+        .iter()
+        .map(|song| {
+            fs::metadata(all_songs_dir.join(&song.file_path))
+                .map(|m| m.len())
+                .ok()
+                .or(song.file_size)
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let mut by_size: Vec<(u64, &Song)> = sizes.iter().copied().zip(songs.iter()).collect();
+    by_size.sort_by_key(|(size, _)| std::cmp::Reverse(*size));
+
+    Ok(LibraryStats {
+        total_songs: songs.len(),
+        total_duration_secs: songs
+            .iter()
+            .filter_map(|s| s.duration_secs)
+            .map(u64::from)
+            .sum(),
+        total_size_bytes: sizes.iter().sum(),
+        artists: count_by(&songs, |s| s.artist.clone()),
+        albums: count_albums(&songs),
+        genres: count_by(&songs, |s| {
+            s.genre.clone().unwrap_or_else(|| UNKNOWN.to_string())
+        }),
+        formats: count_by(&songs, format_of),
+        years: count_by(&songs, |s| {
+            s.year
+                .map(|y| y.to_string())
+                .unwrap_or_else(|| UNKNOWN.to_string())
+        }),
+        biggest_files: by_size
+            .into_iter()
+            .take(BIGGEST_FILES_LIMIT)
+            .map(|(_, song)| song.clone())
+            .collect(),
+        missing_metadata: songs
+            .iter()
+            .filter(|s| s.artist == "Unknown Artist" || s.album == "Unknown Album")
+            .cloned()
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::song_with;
+    use serde_json::json;
+
+    #[test]
+    fn albums_are_counted_per_artist() {
+        let songs = [
+            song_with("a", json!({ "album": "Greatest Hits", "artist": "Queen" })),
+            song_with("b", json!({ "album": "Greatest Hits", "artist": "Queen" })),
+            song_with("c", json!({ "album": "Greatest Hits", "artist": "ABBA" })),
+        ];
+
+        let counts: Vec<(String, Option<String>, usize)> = count_albums(&songs)
+            .into_iter()
+            .map(|e| (e.name, e.artist, e.count))
+            .collect();
+
+        assert_eq!(
+            counts,
+            [
+                ("Greatest Hits".to_string(), Some("Queen".to_string()), 2),
+                ("Greatest Hits".to_string(), Some("ABBA".to_string()), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn format_falls_back_to_the_extension() {
+        assert_eq!(
+            format_of(&song_with("a", json!({ "codec": "FLAC" }))),
+            "FLAC"
+        );
+        assert_eq!(
+            format_of(&song_with("a", json!({ "file_path": "a.ogg" }))),
+            "OGG"
+        );
+        assert_eq!(
+            format_of(&song_with("a", json!({ "file_path": "a" }))),
+            UNKNOWN
+        );
+    }
+}
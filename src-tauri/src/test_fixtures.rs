@@ -0,0 +1,65 @@
+use crate::library::Library;
+use crate::migrations;
+use crate::models::{Playlist, PlaylistEntry, PlaylistFolder, Song};
+use rusqlite::Connection;
+use serde_json::{json, Value};
+use std::path::Path;
+
+/// A song titled after its ID, by "Artist" on "Album", whose file is "<id>.mp3"
+pub fn song(id: &str) -> Song {
+    song_with(id, json!({}))
+}
+
+/// A song as `song` makes it, with the given fields replaced, e.g. `json!({ "genre": "House" })`
+pub fn song_with(id: &str, fields: Value) -> Song {
+    let mut song = json!({
+        "id": id,
+        "title": id,
+        "artist": "Artist",
+        "album": "Album",
+        "file_path": format!("{}.mp3", id),
+    });
+    if let (Some(song), Value::Object(fields)) = (song.as_object_mut(), fields) {
+        song.extend(fields);
+    }
+
+    serde_json::from_value(song).unwrap()
+}
+
+/// A playlist named after its ID, holding the given songs in order
+pub fn playlist(id: &str, song_ids: &[&str]) -> Playlist {
+    Playlist {
+        id: id.to_string(),
+        name: id.to_string(),
+        entries: song_ids.iter().map(|id| PlaylistEntry::new(id)).collect(),
+        created_at: 0,
+        smart: None,
+        folder_id: None,
+        description: None,
+        cover: None,
+        updated_at: 0,
+        pinned: false,
+    }
+}
+
+/// A folder named after its ID, inside the given parent folder
+pub fn folder(id: &str, parent_id: Option<&str>) -> PlaylistFolder {
+    PlaylistFolder {
+        id: id.to_string(),
+        name: id.to_string(),
+        parent_id: parent_id.map(str::to_string),
+        created_at: 0,
+    }
+}
+
+/// Open the library database in an app data directory, creating it if needed
+pub fn open_database(data_dir: &Path) -> Connection {
+    let mut conn = Connection::open(data_dir.join("library.db")).unwrap();
+    migrations::run_migrations(&mut conn, &data_dir.join("backups")).unwrap();
+    conn
+}
+
+/// Load the library kept in an app data directory
+pub fn open_library(data_dir: &Path) -> Library {
+    Library::load(open_database(data_dir), data_dir.to_path_buf()).unwrap()
+}
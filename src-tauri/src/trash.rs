@@ -0,0 +1,458 @@
+use crate::filesystem;
+use crate::fingerprint;
+use crate::library::{Library, Writer};
+use crate::models::{PlaylistEntry, Song, TrashedEntry, TrashedSong};
+use crate::waveform;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tauri::Manager;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Folder holding a trashed song's audio file
+fn trashed_song_dir(song_id: &str, data_dir: &Path) -> PathBuf {
+    data_dir.join("trash").join(song_id)
+}
+
+/// Where a trashed song's audio file is kept
+fn trashed_file_path(song: &Song, data_dir: &Path) -> Result<PathBuf, String> {
+    let file_name = PathBuf::from(&song.file_path)
+        .file_name()
+        .ok_or_else(|| "Invalid song path".to_string())?
+        .to_owned();
+
+    Ok(trashed_song_dir(&song.id, data_dir).join(file_name))
+}
+
+/// Move a song to the trash, removing it from the library and all playlists
+pub fn trash_song(song_id: &str, library: &mut Writer) -> Result<(), String> {
+    let song = library.get_song(song_id)?.clone();
+    let data_dir = library.data_dir().to_path_buf();
+    let song_path = data_dir.join("music/all_songs").join(&song.file_path);
+
+    // Remember every position so restoring puts it back where it was
+    let mut entries = Vec::new();
+    for playlist_id in library.playlists_containing(song_id) {
+        let mut playlist = library.get_playlist(&playlist_id)?.clone();
+
+        entries.extend(
+            playlist
+                .entries
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| entry.song_id == song_id)
+                .map(|(position, entry)| TrashedEntry {
+                    playlist_id: playlist_id.clone(),
+                    position,
+                    entry_id: Some(entry.id.clone()),
+                }),
+        );
+
+        playlist.remove_song(song_id);
+        library.save_playlist(&playlist)?;
+    }
+
+    library.delete_song(song_id)?;
+    library.save_trashed_song(&TrashedSong {
+        song: song.clone(),
+        deleted_at: now_secs(),
+        playlists: entries,
+    })?;
+
+    // Move the audio once the change is saved. If that fails the file stays where it was,
+    // and restoring or purging the song finds it there.
+    let song_dir = trashed_song_dir(&song.id, &data_dir);
+    let trashed_path = trashed_file_path(&song, &data_dir)?;
+    library.after_commit(move || {
+        if song_path.exists() {
+            fs::create_dir_all(&song_dir)
+                .map_err(|e| format!("Failed to create trash directory: {}", e))?;
+            fs::rename(&song_path, &trashed_path)
+                .map_err(|e| format!("Failed to move song to trash: {}", e))?;
+        }
+
+        Ok(())
+    });
+
+    Ok(())
+}
+
+/// Permanently delete a trashed song and any cached analysis nothing else needs
+fn purge_song(song_id: &str, library: &mut Writer) -> Result<(), String> {
+    let trashed = library
+        .trash
+        .iter()
+        .find(|t| t.song.id == song_id)
+        .cloned()
+        .ok_or_else(|| format!("Song not in trash: {}", song_id))?;
+
+    library.delete_trashed_song(song_id)?;
+
+    // Keep the cache if the same audio is still in the library or the trash
+    let unused_hash = trashed.song.file_hash.clone().filter(|file_hash| {
+        !library.file_hash_in_use(file_hash, song_id)
+            && !library
+                .trash
+                .iter()
+                .any(|t| t.song.file_hash.as_deref() == Some(file_hash.as_str()))
+    });
+
+    // If the audio never made it to the trash it's still at its old path, unless a
+    // library song has been given that path since
+    let data_dir = library.data_dir().to_path_buf();
+    let original_path = data_dir
+        .join("music/all_songs")
+        .join(&trashed.song.file_path);
+    let original_path =
+        (!library.file_path_in_use(&trashed.song.file_path)).then_some(original_path);
+
+    // Delete files once the change is saved, so a failure purging another song
+    // can't leave the trash listing songs whose audio is gone
+    let song_dir = trashed_song_dir(song_id, &data_dir);
+    let trashed_path = trashed_file_path(&trashed.song, &data_dir)?;
+    library.after_commit(move || {
+        if let Some(original_path) = original_path {
+            if !trashed_path.exists() && original_path.exists() {
+                fs::remove_file(&original_path)
+                    .map_err(|e| format!("Failed to delete trashed song: {}", e))?;
+            }
+        }
+
+        if song_dir.exists() {
+            fs::remove_dir_all(&song_dir)
+                .map_err(|e| format!("Failed to delete trashed song: {}", e))?;
+        }
+
+        if let Some(file_hash) = unused_hash {
+            waveform::remove_cached_waveform(&file_hash, &data_dir)?;
+            fingerprint::remove_cached_fingerprint(&file_hash, &data_dir)?;
+        }
+
+        Ok(())
+    });
+
+    Ok(())
+}
+
+/// List trashed songs, most recently deleted first
+pub fn list_trash(app_handle: &tauri::AppHandle) -> Result<Vec<TrashedSong>, String> {
+    app_handle
+        .state::<Library>()
+        .read(|library| Ok(library.trash.iter().rev().cloned().collect()))
+}
+
+/// Put a trashed song back in the library and the playlists it was in.
+/// Looks for the audio in the trash, so run it with `Library::write_settled`.
+fn restore_song(song_id: &str, library: &mut Writer) -> Result<Song, String> {
+    let trashed = library
+        .trash
+        .iter()
+        .find(|t| t.song.id == song_id)
+        .cloned()
+        .ok_or_else(|| format!("Song not in trash: {}", song_id))?;
+
+    if library.find_song(song_id).is_some() {
+        return Err(format!("Song is already in the library: {}", song_id));
+    }
+
+    // The original file name may have been taken since, on disk or by a song whose file
+    // hasn't been moved in yet. If the audio never made it to the trash, it's still at its
+    // old path.
+    let mut song = trashed.song;
+    let data_dir = library.data_dir().to_path_buf();
+    let trashed_path = trashed_file_path(&song, &data_dir)?;
+    let destination = if trashed_path.exists() {
+        let (dest_path, relative_path) =
+            filesystem::free_all_songs_path(&trashed_path, &data_dir, |path| {
+                library.file_path_in_use(path)
+            })?;
+        song.file_path = relative_path;
+        Some(dest_path)
+    } else {
+        None
+    };
+    library.save_song(&song)?;
+
+    // Insert in order of position so earlier entries don't shift later ones
+    let mut entries = trashed.playlists;
+    entries.sort_by_key(|e| e.position);
+
+    for entry in entries {
+        let mut playlist = match library.find_playlist(&entry.playlist_id) {
+            Some(playlist) => playlist.clone(),
+            None => continue,
+        };
+
+        let position = entry.position.min(playlist.entries.len());
+        let playlist_entry = match entry.entry_id {
+            Some(id) => PlaylistEntry {
+                id,
+                song_id: song.id.clone(),
+            },
+            None => PlaylistEntry::new(&song.id),
+        };
+        playlist.entries.insert(position, playlist_entry);
+        library.save_playlist(&playlist)?;
+    }
+
+    library.delete_trashed_song(song_id)?;
+
+    // Move the audio once the change is saved, never over a file that's appeared since.
+    // If that fails the file stays in the trash folder and the song is reported missing.
+    let song_dir = trashed_song_dir(song_id, &data_dir);
+    library.after_commit(move || {
+        if let Some(dest_path) = destination {
+            if dest_path.exists() {
+                return Err(format!(
+                    "Failed to restore song file: {} already exists",
+                    dest_path.display()
+                ));
+            }
+            fs::rename(&trashed_path, &dest_path)
+                .map_err(|e| format!("Failed to restore song file: {}", e))?;
+        }
+
+        if song_dir.exists() {
+            fs::remove_dir_all(&song_dir)
+                .map_err(|e| format!("Failed to clean up trash directory: {}", e))?;
+        }
+
+        Ok(())
+    });
+
+    Ok(song)
+}
+
+/// Put trashed songs back in the library and the playlists they were in.
+/// Each song is restored on its own, so if one fails the songs before it stay restored.
+pub fn restore_from_trash(
+    song_ids: Vec<String>,
+    app_handle: &tauri::AppHandle,
+) -> Result<Vec<Song>, String> {
+    let library = app_handle.state::<Library>();
+
+    let mut restored = Vec::new();
+    for song_id in &song_ids {
+        restored.push(library.write_settled(|library| restore_song(song_id, library))?);
+    }
+
+    Ok(restored)
+}
+
+/// Permanently delete everything in the trash
+pub fn empty_trash(app_handle: &tauri::AppHandle) -> Result<(), String> {
+    app_handle.state::<Library>().write(|library| {
+        let song_ids: Vec<String> = library.trash.iter().map(|t| t.song.id.clone()).collect();
+
+        for song_id in song_ids {
+            purge_song(&song_id, library)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Permanently delete songs that have been in the trash longer than the retention period.
+/// Returns how many were deleted.
+pub fn purge_expired(app_handle: &tauri::AppHandle) -> Result<usize, String> {
+    app_handle.state::<Library>().write(|library| {
+        let retention_days = library.settings.trash_retention_days as u64;
+        if retention_days == 0 {
+            return Ok(0);
+        }
+
+        let cutoff = now_secs().saturating_sub(retention_days * SECS_PER_DAY);
+        let expired: Vec<String> = library
+            .trash
+            .iter()
+            .filter(|t| t.deleted_at < cutoff)
+            .map(|t| t.song.id.clone())
+            .collect();
+
+        for song_id in &expired {
+            purge_song(song_id, library)?;
+        }
+
+        Ok(expired.len())
+    })
+}
+
+/// Get how many days songs stay in the trash
+pub fn get_trash_retention_days(app_handle: &tauri::AppHandle) -> Result<u32, String> {
+    app_handle
+        .state::<Library>()
+        .read(|library| Ok(library.settings.trash_retention_days))
+}
+
+/// Set how many days songs stay in the trash (0 = until emptied), purging any now expired
+pub fn set_trash_retention_days(days: u32, app_handle: &tauri::AppHandle) -> Result<(), String> {
+    app_handle.state::<Library>().write(|library| {
+        let mut settings = library.settings.clone();
+        settings.trash_retention_days = days;
+        library.save_settings(&settings)
+    })?;
+
+    purge_expired(app_handle)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{open_library, playlist, song};
+
+    /// Songs a and b, both in mix, with a's file on disk
+    fn setup(data_dir: &Path) -> Library {
+        let all_songs_dir = data_dir.join("music/all_songs");
+        fs::create_dir_all(&all_songs_dir).unwrap();
+        fs::write(all_songs_dir.join("a.mp3"), b"a").unwrap();
+
+        let library = open_library(data_dir);
+        library
+            .write(|library| {
+                library.save_song(&song("a"))?;
+                library.save_song(&song("b"))?;
+                library.save_playlist(&playlist("mix", &["b", "a"]))
+            })
+            .unwrap();
+
+        library
+    }
+
+    fn entry_ids(library: &Library) -> Vec<String> {
+        library
+            .read(|library| {
+                let entries = &library.get_playlist("mix")?.entries;
+                Ok(entries.iter().map(|e| e.id.clone()).collect())
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn trash_and_restore_put_everything_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = setup(dir.path());
+        let all_songs_dir = dir.path().join("music/all_songs");
+        let before = entry_ids(&library);
+
+        library.write(|library| trash_song("a", library)).unwrap();
+        assert!(!all_songs_dir.join("a.mp3").exists());
+        assert!(dir.path().join("trash/a/a.mp3").exists());
+        assert_eq!(entry_ids(&library), before[..1]);
+
+        let restored = library
+            .write_settled(|library| restore_song("a", library))
+            .unwrap();
+        assert_eq!(restored.file_path, "a.mp3");
+        assert_eq!(fs::read(all_songs_dir.join("a.mp3")).unwrap(), b"a");
+        assert!(!dir.path().join("trash/a").exists());
+        assert_eq!(entry_ids(&library), before);
+        assert!(library.background_errors().is_empty());
+    }
+
+    #[test]
+    fn restore_skips_names_taken_on_disk_or_by_songs() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = setup(dir.path());
+        let all_songs_dir = dir.path().join("music/all_songs");
+
+        library.write(|library| trash_song("a", library)).unwrap();
+
+        // A new a.mp3 is on disk, and a song has claimed a_1.mp3 before its file arrived
+        fs::write(all_songs_dir.join("a.mp3"), b"new").unwrap();
+        let claimed = Song {
+            file_path: "a_1.mp3".to_string(),
+            ..song("c")
+        };
+        library
+            .write(|library| library.save_song(&claimed))
+            .unwrap();
+
+        let restored = library
+            .write_settled(|library| restore_song("a", library))
+            .unwrap();
+        assert_eq!(restored.file_path, "a_2.mp3");
+        assert_eq!(fs::read(all_songs_dir.join("a_2.mp3")).unwrap(), b"a");
+        assert_eq!(fs::read(all_songs_dir.join("a.mp3")).unwrap(), b"new");
+    }
+
+    #[test]
+    fn restore_never_overwrites_a_file_that_appears_meanwhile() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = setup(dir.path());
+        let all_songs_dir = dir.path().join("music/all_songs");
+
+        library.write(|library| trash_song("a", library)).unwrap();
+        library
+            .write_settled(|library| {
+                let song = restore_song("a", library)?;
+                fs::write(all_songs_dir.join(&song.file_path), b"new").unwrap();
+                Ok(())
+            })
+            .unwrap();
+
+        // The song is back, but its audio stays in the trash folder
+        assert_eq!(fs::read(all_songs_dir.join("a.mp3")).unwrap(), b"new");
+        assert!(dir.path().join("trash/a/a.mp3").exists());
+        assert_eq!(library.background_errors().len(), 1);
+    }
+
+    #[test]
+    fn purge_deletes_the_trashed_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = setup(dir.path());
+
+        library.write(|library| trash_song("a", library)).unwrap();
+        library.write(|library| purge_song("a", library)).unwrap();
+
+        assert!(!dir.path().join("trash/a").exists());
+        assert!(!dir.path().join("music/all_songs/a.mp3").exists());
+        let trash = library.read(|library| Ok(library.trash.clone())).unwrap();
+        assert!(trash.is_empty());
+    }
+
+    #[test]
+    fn purge_deletes_a_file_that_never_reached_the_trash() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = setup(dir.path());
+        let song_path = dir.path().join("music/all_songs/a.mp3");
+
+        // A file where the song's trash folder should go makes the move fail
+        fs::create_dir_all(dir.path().join("trash")).unwrap();
+        fs::write(dir.path().join("trash/a"), b"").unwrap();
+
+        library.write(|library| trash_song("a", library)).unwrap();
+        assert!(song_path.exists());
+        assert_eq!(library.background_errors().len(), 1);
+
+        fs::remove_file(dir.path().join("trash/a")).unwrap();
+        library.write(|library| purge_song("a", library)).unwrap();
+        assert!(!song_path.exists());
+    }
+
+    #[test]
+    fn restore_finds_a_file_that_never_reached_the_trash() {
+        let dir = tempfile::tempdir().unwrap();
+        let library = setup(dir.path());
+
+        fs::create_dir_all(dir.path().join("trash")).unwrap();
+        fs::write(dir.path().join("trash/a"), b"").unwrap();
+        library.write(|library| trash_song("a", library)).unwrap();
+        fs::remove_file(dir.path().join("trash/a")).unwrap();
+
+        let restored = library
+            .write_settled(|library| restore_song("a", library))
+            .unwrap();
+        assert_eq!(restored.file_path, "a.mp3");
+        assert!(dir.path().join("music/all_songs/a.mp3").exists());
+    }
+}
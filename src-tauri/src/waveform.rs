@@ -0,0 +1,214 @@
+use crate::audio;
+use crate::filesystem;
+use crate::library::Library;
+use crate::metadata;
+use crate::models::{Song, Waveform};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::Manager;
+
+/// Number of peaks stored in the on-disk cache; requests are downsampled from this
+const CACHED_RESOLUTION: usize = 2048;
+
+/// Number of samples folded into each peak while decoding
+const BLOCK_SIZE: usize = 256;
+
+/// Decode an audio file and compute min/max peaks at the given resolution
+pub fn generate_peaks(file_path: &Path, resolution: usize) -> Result<Waveform, String> {
+    let mut block_min = Vec::new();
+    let mut block_max = Vec::new();
+    let mut current_min = f32::MAX;
+    let mut current_max = f32::MIN;
+    let mut count = 0;
+
+    // The total length isn't known up front, so collect fixed-size blocks first
+    audio::decode_mono(file_path, None, |samples, _| {
+        for &sample in samples {
+            current_min = current_min.min(sample);
+            current_max = current_max.max(sample);
+            count += 1;
+
+            if count == BLOCK_SIZE {
+                block_min.push(current_min);
+                block_max.push(current_max);
+                current_min = f32::MAX;
+                current_max = f32::MIN;
+                count = 0;
+            }
+        }
+    })?;
+
+    if count > 0 {
+        block_min.push(current_min);
+        block_max.push(current_max);
+    }
+
+    Ok(downsample_peaks(&block_min, &block_max, resolution))
+}
+
+/// Reduce min/max peak arrays to exactly `resolution` buckets
+fn downsample_peaks(min: &[f32], max: &[f32], resolution: usize) -> Waveform {
+    let len = min.len().min(max.len());
+
+    if len == 0 {
+        return Waveform {
+            resolution,
+            min: vec![0.0; resolution],
+            max: vec![0.0; resolution],
+        };
+    }
+
+    let mut out_min = Vec::with_capacity(resolution);
+    let mut out_max = Vec::with_capacity(resolution);
+
+    for i in 0..resolution {
+        let start = (i * len / resolution).min(len - 1);
+        let end = ((i + 1) * len / resolution).clamp(start + 1, len);
+
+        out_min.push(min[start..end].iter().copied().fold(f32::MAX, f32::min));
+        out_max.push(max[start..end].iter().copied().fold(f32::MIN, f32::max));
+    }
+
+    Waveform {
+        resolution,
+        min: out_min,
+        max: out_max,
+    }
+}
+
+/// Get the path of the cached waveform for a file hash
+fn waveform_cache_path(file_hash: &str, data_dir: &Path) -> PathBuf {
+    data_dir
+        .join("waveforms")
+        .join(format!("{}.json", file_hash))
+}
+
+/// Load a cached waveform, if one exists
+fn load_cached_waveform(
+    file_hash: &str,
+    app_handle: &tauri::AppHandle,
+) -> Result<Option<Waveform>, String> {
+    let data_dir = filesystem::get_app_data_path(app_handle)?;
+    let cache_path = waveform_cache_path(file_hash, &data_dir);
+
+    if !cache_path.exists() {
+        return Ok(None);
+    }
+
+    let contents = fs::read_to_string(&cache_path)
+        .map_err(|e| format!("Failed to read waveform cache: {}", e))?;
+
+    // A corrupt cache entry is simply regenerated
+    Ok(serde_json::from_str(&contents).ok())
+}
+
+/// Save a waveform to the cache
+fn save_cached_waveform(
+    file_hash: &str,
+    waveform: &Waveform,
+    app_handle: &tauri::AppHandle,
+) -> Result<(), String> {
+    let data_dir = filesystem::get_app_data_path(app_handle)?;
+    let cache_path = waveform_cache_path(file_hash, &data_dir);
+
+    if let Some(parent) = cache_path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create waveform directory: {}", e))?;
+    }
+
+    let json = serde_json::to_string(waveform)
+        .map_err(|e| format!("Failed to serialize waveform: {}", e))?;
+
+    fs::write(&cache_path, json).map_err(|e| format!("Failed to write waveform cache: {}", e))?;
+
+    Ok(())
+}
+
+/// Get the full-resolution cached waveform for a song, generating it if needed
+fn ensure_cached_waveform(song: &Song, app_handle: &tauri::AppHandle) -> Result<Waveform, String> {
+    let file_hash = metadata::ensure_file_hash(song, app_handle)?;
+
+    if let Some(waveform) = load_cached_waveform(&file_hash, app_handle)? {
+        return Ok(waveform);
+    }
+
+    let absolute_path = filesystem::get_song_absolute_path(&song.file_path, app_handle)?;
+    let waveform = generate_peaks(&absolute_path, CACHED_RESOLUTION)?;
+    save_cached_waveform(&file_hash, &waveform, app_handle)?;
+
+    Ok(waveform)
+}
+
+/// Get the waveform for a song at the requested resolution, at most `CACHED_RESOLUTION` points
+pub fn get_waveform(
+    song_id: String,
+    resolution: usize,
+    app_handle: &tauri::AppHandle,
+) -> Result<Waveform, String> {
+    if resolution == 0 || resolution > CACHED_RESOLUTION {
+        return Err(format!(
+            "Waveform resolution must be between 1 and {}",
+            CACHED_RESOLUTION
+        ));
+    }
+
+    let song = app_handle
+        .state::<Library>()
+        .read(|library| library.get_song(&song_id).cloned())?;
+
+    let cached = ensure_cached_waveform(&song, app_handle)?;
+
+    Ok(downsample_peaks(&cached.min, &cached.max, resolution))
+}
+
+/// Generate cached waveforms for the given songs on a background thread
+pub fn generate_in_background(songs: Vec<Song>, app_handle: tauri::AppHandle) {
+    tauri::async_runtime::spawn_blocking(move || {
+        for song in songs {
+            if let Err(e) = ensure_cached_waveform(&song, &app_handle) {
+                app_handle.state::<Library>().report_error(format!(
+                    "Failed to generate waveform for {}: {}",
+                    song.id, e
+                ));
+            }
+        }
+    });
+}
+
+/// Remove the cached waveform for a file hash
+pub fn remove_cached_waveform(file_hash: &str, data_dir: &Path) -> Result<(), String> {
+    let cache_path = waveform_cache_path(file_hash, data_dir);
+
+    if cache_path.exists() {
+        fs::remove_file(&cache_path)
+            .map_err(|e| format!("Failed to delete waveform cache: {}", e))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downsampling_keeps_the_extremes_of_each_bucket() {
+        let min = [-0.1, -0.5, -0.2, -0.9];
+        let max = [0.3, 0.1, 0.8, 0.2];
+
+        let waveform = downsample_peaks(&min, &max, 2);
+        assert_eq!(waveform.min, [-0.5, -0.9]);
+        assert_eq!(waveform.max, [0.3, 0.8]);
+    }
+
+    #[test]
+    fn upsampling_repeats_peaks_and_silence_is_flat() {
+        let waveform = downsample_peaks(&[-0.5], &[0.5], 3);
+        assert_eq!(waveform.min, [-0.5; 3]);
+        assert_eq!(waveform.max, [0.5; 3]);
+
+        let waveform = downsample_peaks(&[], &[], 4);
+        assert_eq!(waveform.min, [0.0; 4]);
+        assert_eq!(waveform.max, [0.0; 4]);
+    }
+}
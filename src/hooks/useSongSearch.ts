@@ -0,0 +1,39 @@
+import { useEffect, useState } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+import { useAppState } from '../store/appStore';
+import type { Song, SongMatch } from '../types';
+
+// Narrow songs down to those matching a search query, best match first. Matching is
+// done by the backend so every view searches the same way.
+export function useSongSearch(songs: Song[], query: string): Song[] {
+  const { songs: librarySongs } = useAppState();
+  const [matchIds, setMatchIds] = useState<string[] | null>(null);
+
+  useEffect(() => {
+    if (!query) {
+      setMatchIds(null);
+      return;
+    }
+
+    let cancelled = false;
+    invoke<SongMatch[]>('search_songs', { query })
+      .then((matches) => {
+        if (!cancelled) setMatchIds(matches.map((m) => m.song.id));
+      })
+      .catch((error) => {
+        // Half-typed queries, like an unclosed quote, don't parse; keep the last results
+        console.error('Search failed:', error);
+      });
+
+    return () => {
+      cancelled = true;
+    };
+  }, [query, librarySongs]);
+
+  if (!query || matchIds === null) return songs;
+
+  const songsById = new Map(songs.map((s) => [s.id, s]));
+  return matchIds
+    .map((id) => songsById.get(id))
+    .filter((s): s is Song => s !== undefined);
+}
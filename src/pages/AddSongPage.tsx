@@ -4,7 +4,7 @@ import { CirclePlus } from 'lucide-react'
 import GridPageLayout from '../components/GridPageLayout'
 import ActionButton from '../components/ActionButton'
 import { useAppState } from '../store/appStore'
-import { fuzzyMatch } from '../util/search'
+import { useSongSearch } from '../hooks/useSongSearch'
 import type { Song } from '../types'
 
 const AddSongPage: FC = () => {
@@ -18,14 +18,7 @@ const AddSongPage: FC = () => {
         ? songs.filter((s) => !currentPlaylist.song_ids.includes(s.id))
         : songs
 
-    const filteredSongs = searchQuery
-        ? availableSongs.filter(
-              (s) =>
-                  fuzzyMatch(s.title, searchQuery) ||
-                  fuzzyMatch(s.artist, searchQuery) ||
-                  (s.album && fuzzyMatch(s.album, searchQuery))
-          )
-        : availableSongs
+    const filteredSongs = useSongSearch(availableSongs, searchQuery)
 
     const handlePathNavigate = (index: number) => {
         if (index === 0) {
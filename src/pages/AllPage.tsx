@@ -1,4 +1,4 @@
-import { FC, useState } from 'react'
+import { FC, useEffect, useState } from 'react'
 import { invoke } from '@tauri-apps/api/core'
 import { FolderPlus } from 'lucide-react'
 import { DragDropContext, Droppable, Draggable, DropResult } from '@hello-pangea/dnd'
@@ -6,7 +6,7 @@ import GridPageLayout from '../components/GridPageLayout'
 import PlaylistCard from '../components/PlaylistCard'
 import ActionButton from '../components/ActionButton'
 import { useAppState } from '../store/appStore'
-import { fuzzyMatch } from '../util/search'
+import type { Playlist } from '../types'
 
 const AllPage: FC = () => {
     const { setPath, songs, playlists, refreshPlaylists, updatePlaylistName, reorderPlaylists } = useAppState()
@@ -115,9 +115,31 @@ const AllPage: FC = () => {
         }
     }
 
-    const filteredPlaylists = searchQuery
-        ? playlists.filter((p) => fuzzyMatch(p.name, searchQuery))
-        : playlists
+    // Search in the backend so playlist names match the way song searches do
+    const [matchIds, setMatchIds] = useState<Set<string> | null>(null)
+
+    useEffect(() => {
+        if (!searchQuery) {
+            setMatchIds(null)
+            return
+        }
+
+        let cancelled = false
+        invoke<Playlist[]>('search_playlists', { query: searchQuery })
+            .then((matches) => {
+                if (!cancelled) setMatchIds(new Set(matches.map((p) => p.id)))
+            })
+            .catch((error) => console.error('Failed to search playlists:', error))
+
+        return () => {
+            cancelled = true
+        }
+    }, [searchQuery, playlists])
+
+    const filteredPlaylists =
+        searchQuery && matchIds
+            ? playlists.filter((p) => matchIds.has(p.id))
+            : playlists
 
     const playlistsContent = (
         <>
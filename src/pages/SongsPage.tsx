@@ -7,7 +7,7 @@ import SongCard from '../components/SongCard'
 import ActionButton from '../components/ActionButton'
 import { useAppState } from '../store/appStore'
 import { useQueue } from '../hooks/useQueue'
-import { fuzzyMatch } from '../util/search'
+import { useSongSearch } from '../hooks/useSongSearch'
 
 const SongsPage: FC = () => {
     const { path, setPath, setView, songs, playlists, refreshPlaylists, refreshSongs, updatePlaylistSongOrder } =
@@ -30,14 +30,7 @@ const SongsPage: FC = () => {
 
     const displaySongs = isAllSongs ? songs : playlistSongs
 
-    const filteredSongs = searchQuery
-        ? displaySongs.filter(
-              (s) =>
-                  fuzzyMatch(s.title, searchQuery) ||
-                  fuzzyMatch(s.artist, searchQuery) ||
-                  (s.album && fuzzyMatch(s.album, searchQuery))
-          )
-        : displaySongs
+    const filteredSongs = useSongSearch(displaySongs, searchQuery)
 
     const handlePathNavigate = (index: number) => {
         if (index === 0) {
@@ -7,16 +7,216 @@ export interface Song {
   album: string;
   file_path: string;
   duration_secs: number;
+  genre?: string;
+  year?: number;
   album_art: string | null; // base64 encoded data URL or null
+  file_hash?: string; // SHA-256 of the audio file
+  codec?: string;
+  bitrate_kbps?: number; // average bitrate for VBR files
+  sample_rate?: number;
+  channel_mode?: string;
+  is_vbr?: boolean;
+  encoder?: string; // e.g. "LAME3.100"
+  file_size?: number;
+  added_at: number; // seconds since the epoch; 0 if unknown
+  track_number?: number;
+  disc_number?: number;
+  album_artist?: string; // albums are grouped by this, or by artist if unset
+  tags_version: number; // which tag fields have been read from the file
 }
 
 export interface Playlist {
   id: string;
   name: string;
   song_ids: string[];
+  entries?: PlaylistEntry[]; // same order as song_ids; a song may appear more than once
   created_at: string;
+  smart?: SmartCriteria; // songs are chosen by these rules and kept up to date
+  folder_id?: string; // unset for playlists at the top level
+  description?: string;
+  cover?: string; // custom cover as a data URL; unset to use get_playlist_cover's mosaic
+  updated_at: number; // seconds since the epoch
+  pinned: boolean;
 }
 
+export interface PlaylistEntry {
+  id: string;
+  song_id: string;
+}
+
+export interface PlaylistFolder {
+  id: string;
+  name: string;
+  parent_id?: string; // unset for folders at the top level
+  created_at: number;
+}
+
+export type TextField = 'title' | 'artist' | 'album' | 'genre' | 'codec';
+
+export type NumberField = 'year' | 'duration_secs' | 'bitrate_kbps';
+
+export type Condition =
+  | { op: 'is' | 'is_not' | 'contains' | 'not_contains'; field: TextField; value: string }
+  | { op: 'greater_than' | 'less_than'; field: NumberField; value: number }
+  | { op: 'between'; field: NumberField; min: number; max: number } // inclusive
+  | { op: 'added_in_last_days'; days: number };
+
+export interface RuleGroup {
+  match_all: boolean; // AND when true, OR when false
+  rules: (RuleGroup | Condition)[];
+}
+
+export interface SmartCriteria {
+  rules: RuleGroup;
+  sort_by?: string;
+  descending?: boolean;
+  limit?: number;
+}
+
+export interface Waveform {
+  resolution: number;
+  min: number[];
+  max: number[];
+}
+
+export interface DuplicateMatch {
+  song: Song;
+  similarity: number; // 0 - 1, relative to the suggested song to keep
+}
+
+export interface DuplicateGroup {
+  keep_id: string; // highest-quality copy in the group
+  matches: DuplicateMatch[];
+}
+
+export interface BackupManifest {
+  format_version: number;
+  created_at: number;
+  song_count: number;
+  playlist_count: number;
+  includes_audio: boolean;
+  missing_song_ids: string[];
+}
+
+export type RestoreMode = 'replace' | 'merge';
+
+export interface RestoreSummary {
+  songs_added: number;
+  songs_removed: number;
+  playlists_added: number;
+  playlists_removed: number;
+  skipped_song_ids: string[]; // audio was neither in the backup nor on disk
+}
+
+export interface DanglingEntry {
+  playlist_id: string;
+  song_id: string;
+}
+
+export interface LibraryReport {
+  orphan_files: string[]; // relative to all_songs
+  missing_files: Song[];
+  dangling_entries: DanglingEntry[];
+  stale_symlinks: string[]; // relative to music/playlists
+  background_errors: string[]; // failures in background work since startup
+}
+
+export type RepairCategory =
+  | 'orphan_files'
+  | 'missing_files'
+  | 'dangling_entries'
+  | 'stale_symlinks';
+
+export interface TrashedEntry {
+  playlist_id: string;
+  position: number;
+  entry_id?: string;
+}
+
+export interface TrashedSong {
+  song: Song; // file_path is where the song lived in all_songs
+  deleted_at: number;
+  playlists: TrashedEntry[];
+}
+
+export interface CountEntry {
+  name: string;
+  artist?: string; // set for albums, which are counted per artist
+  count: number;
+}
+
+export interface LibraryStats {
+  total_songs: number;
+  total_duration_secs: number;
+  total_size_bytes: number;
+  // each list is sorted by count, highest first
+  artists: CountEntry[];
+  albums: CountEntry[];
+  genres: CountEntry[];
+  formats: CountEntry[];
+  years: CountEntry[];
+  biggest_files: Song[];
+  missing_metadata: Song[]; // Unknown Artist or Unknown Album
+}
+
+export interface SyncOptions {
+  remove_stale?: boolean; // delete files synced earlier for these playlists that none has now
+}
+
+export interface SyncSummary {
+  copied: number;
+  unchanged: number;
+  removed: number;
+  playlists_written: number;
+  missing_song_ids: string[];
+}
+
+export interface SongMatch {
+  song: Song;
+  score: number; // higher is a better match; results come best first
+}
+
+export interface Album {
+  id: string;
+  title: string;
+  artist: string; // album artist, or the track artist if untagged
+  year: number | null; // earliest year of its songs
+  song_ids: string[]; // in disc and track order, ready to queue
+  total_duration_secs: number;
+  album_art: string | null;
+}
+
+export interface AlbumDetail {
+  album: Album;
+  songs: Song[]; // same order as album.song_ids
+}
+
+export interface Artist {
+  id: string;
+  name: string;
+  album_ids: string[]; // oldest first
+  song_ids: string[]; // album by album
+  total_duration_secs: number;
+  album_art: string | null;
+}
+
+export interface ArtistDetail {
+  artist: Artist;
+  albums: Album[];
+  songs: Song[]; // same order as artist.song_ids
+}
+
+export interface SortKey {
+  field: string; // a song field such as 'title', 'track' (disc, then track number) or 'random'
+  descending?: boolean;
+}
+
+export type SplitMode =
+  | { by: 'album' }
+  | { by: 'artist' }
+  | { by: 'songs'; count: number } // parts of at most this many songs
+  | { by: 'minutes'; minutes: number }; // parts no longer than this
+
 export type ViewType = 'grid' | 'playing';
 
 export type PathSegment =